@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use md_to_incodoc::*;
+
+fuzz_target!(|input: &str| {
+    for opts in [
+        ConvertOptions::default(),
+        ConvertOptions::default()
+            .micro_section_heading_level(u8::MAX)
+            .heading_ids(true)
+            .html_blocks(HtmlBlockPolicy::Map)
+            .collect_footnotes(true),
+    ] {
+        let doc = parse_md_to_incodoc_with(input, &opts);
+        let mut output = String::new();
+        incodoc::output::doc_out(&doc, &mut output);
+        let _ = doc_to_md(&doc);
+    }
+});
@@ -0,0 +1,95 @@
+use crate::{ Diagnostic, DiagnosticKind };
+
+use incodoc::*;
+
+use std::{
+    collections::{ HashMap, HashSet },
+    ops::Range,
+};
+
+/// Generates GitHub compatible heading ids.
+#[derive(Default)]
+pub(crate) struct Slugger {
+    seen: HashMap<String, usize>,
+}
+
+impl Slugger {
+    /// Reserve an explicit id so no generated id takes it.
+    pub(crate) fn reserve(&mut self, id: &str) {
+        self.seen.entry(id.to_string()).or_insert(0);
+    }
+
+    /// Slug of the text, made unique by suffixing "-1", "-2", ... if needed.
+    pub(crate) fn slug(&mut self, text: &str) -> String {
+        let base: String = text
+            .to_lowercase()
+            .chars()
+            .filter(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_'))
+            .map(|c| if c == ' ' { '-' } else { c })
+            .collect();
+        let mut slug = base.clone();
+        while let Some(count) = self.seen.get_mut(&slug) {
+            *count += 1;
+            slug = format!("{base}-{count}");
+        }
+        self.seen.insert(slug.clone(), 0);
+        slug
+    }
+}
+
+pub(crate) fn heading_text(head: &Heading) -> String {
+    let mut text = String::new();
+    for item in &head.items {
+        match item {
+            EmOrText::Text(t) => text.push_str(t),
+            EmOrText::Em(em) => text.push_str(&em.text),
+        }
+    }
+    text
+}
+
+fn collect_section_ids(section: &Section, ids: &mut HashSet<String>) {
+    for props in [&section.props, &section.heading.props] {
+        if let Some(PropVal::String(id)) = props.get("id") {
+            ids.insert(id.clone());
+        }
+    }
+    for item in &section.items {
+        if let SectionItem::Section(sub) = item {
+            collect_section_ids(sub, ids);
+        }
+    }
+}
+
+/// Tag links to anchors that no section, heading or link in the document has as id.
+/// `anchor_links` are the urls and source ranges of all links starting with '#'.
+pub(crate) fn resolve_anchors(
+    doc: &mut Doc, anchor_links: &[(String, Range<usize>)], diagnostics: &mut Vec<Diagnostic>
+) {
+    let mut ids = HashSet::new();
+    for item in &doc.items {
+        if let DocItem::Section(section) = item {
+            collect_section_ids(section, &mut ids);
+        }
+    }
+    // such as footnote references, which footnote definitions link back to
+    for link in doc.links_mut(false) {
+        if let Some(PropVal::String(id)) = link.props.get("id") {
+            ids.insert(id.clone());
+        }
+    }
+    let exists = |url: &str| url.strip_prefix('#').is_none_or(|id| ids.contains(id));
+    for (url, range) in anchor_links {
+        if !exists(url) {
+            diagnostics.push(Diagnostic {
+                kind: DiagnosticKind::BrokenAnchor(url.clone()),
+                range: range.clone(),
+            });
+        }
+    }
+    for link in doc.links_mut(false) {
+        if !link.tags.contains("footnote-ref") && !exists(&link.url) {
+            link.tags.insert("broken-anchor".to_string());
+        }
+    }
+}
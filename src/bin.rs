@@ -1,31 +1,227 @@
-use md_to_incodoc::parse_md_to_incodoc;
-use incodoc::output::doc_out;
+use md_to_incodoc::*;
+use incodoc::parsing::parse;
 
-const INPUT: &str =
-"
-# H1
+use std::{
+    env,
+    fs,
+    io::{ self, Read, Write },
+    path::PathBuf,
+    process::ExitCode,
+};
 
-line
+const USAGE: &str =
+"Usage: md-to-incodoc [OPTIONS] [INPUT]
 
-## H2
+Convert markdown to incodoc.
+Reads INPUT, or stdin if INPUT is omitted or '-'.
+Writes to stdout unless an output file is given.
 
-par.
-par.
-par.
+Options:
+  -o, --output FILE        write to FILE instead of stdout
+      --enable EXTS        enable comma separated markdown extensions
+      --disable EXTS       disable comma separated markdown extensions
+      --code-tag TAG       tag for inline code (default: code)
+      --math-tag TAG       tag for math (default: latex-math)
+      --unconv-tag TAG     tag for unconverted content (default: unconv-corp)
+      --micro-level N      heading level of micro sections (default: 100)
+      --html-blocks MODE   code, map, text or drop (default: code)
+      --inline-html MODE   enclose or strip (default: enclose)
+      --positions          add source byte range props
+      --line-columns       with --positions, also add source line and column props
+      --heading-ids        generate heading ids and check links to anchors
+      --wikilink-dir DIR   resolve wikilinks to DIR/PAGE.md, reporting missing pages
+      --collect-footnotes  gather footnote definitions into a numbered section at the end
+      --link-defs          keep link reference definitions in a nav
+      --link-types         record the markdown syntax of links
+      --link-ext FROM=TO   replace extension FROM of relative link paths by TO
+      --base-url URL       resolve relative links against URL
+      --to-markdown        convert incodoc to markdown instead
+  -q, --quiet              do not report lossy conversions on stderr
+  -h, --help               print this help
 
-- list item
+Extensions:
+  tables, footnotes, old-footnotes, strikethrough, tasklists, smart-punctuation,
+  heading-attributes, yaml-metadata, pluses-metadata, math, gfm, definition-list,
+  superscript, subscript, wikilinks, all
 
-> first
-> second
-> third
+Exit codes:
+  0 success, 1 i/o error, 2 usage error, 3 conversion error
 ";
 
-fn main() {
-    let mut doc = parse_md_to_incodoc(INPUT);
-    // doc.squash();
-    // println!("{:#?}", doc);
-    let mut output = String::new();
-    doc_out(&doc, &mut output);
-    println!("{output}");
+const EXIT_IO: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_CONVERSION: u8 = 3;
+
+struct Args {
+    input: Option<String>,
+    output: Option<String>,
+    quiet: bool,
+    to_markdown: bool,
+    opts: ConvertOptions,
+}
+
+fn extension(name: &str) -> Option<Options> {
+    Some(match name {
+        "tables" => Options::ENABLE_TABLES,
+        "footnotes" => Options::ENABLE_FOOTNOTES,
+        "old-footnotes" => Options::ENABLE_OLD_FOOTNOTES,
+        "strikethrough" => Options::ENABLE_STRIKETHROUGH,
+        "tasklists" => Options::ENABLE_TASKLISTS,
+        "smart-punctuation" => Options::ENABLE_SMART_PUNCTUATION,
+        "heading-attributes" => Options::ENABLE_HEADING_ATTRIBUTES,
+        "yaml-metadata" => Options::ENABLE_YAML_STYLE_METADATA_BLOCKS,
+        "pluses-metadata" => Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS,
+        "math" => Options::ENABLE_MATH,
+        "gfm" => Options::ENABLE_GFM,
+        "definition-list" => Options::ENABLE_DEFINITION_LIST,
+        "superscript" => Options::ENABLE_SUPERSCRIPT,
+        "subscript" => Options::ENABLE_SUBSCRIPT,
+        "wikilinks" => Options::ENABLE_WIKILINKS,
+        "all" => Options::all(),
+        _ => return None,
+    })
 }
 
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Args>, String> {
+    let mut res = Args {
+        input: None,
+        output: None,
+        quiet: false,
+        to_markdown: false,
+        opts: ConvertOptions::default(),
+    };
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("missing value for {name}"));
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-o" | "--output" => res.output = Some(value(&arg)?),
+            "-q" | "--quiet" => res.quiet = true,
+            "--to-markdown" => res.to_markdown = true,
+            "--enable" | "--disable" => {
+                let enabled = arg == "--enable";
+                for name in value(&arg)?.split(',') {
+                    let ext = extension(name).ok_or(format!("unknown extension: {name}"))?;
+                    res.opts.extensions.set(ext, enabled);
+                }
+            },
+            "--positions" => res.opts.source_positions = true,
+            "--line-columns" => res.opts.line_columns = true,
+            "--heading-ids" => res.opts.heading_ids = true,
+            "--collect-footnotes" => res.opts.collect_footnotes = true,
+            "--link-defs" => res.opts.link_definitions = true,
+            "--link-types" => res.opts.link_types = true,
+            "--link-ext" => {
+                let mapping = value(&arg)?;
+                let (from, to) = mapping.split_once('=')
+                    .ok_or(format!("expected FROM=TO for --link-ext: {mapping}"))?;
+                res.opts = res.opts.link_extension(from, to);
+            },
+            "--base-url" => res.opts.base_url = Some(value(&arg)?),
+            "--wikilink-dir" => {
+                let dir = PathBuf::from(value(&arg)?);
+                res.opts.wikilink_resolver = Some(WikilinkResolver::new(move |page| {
+                    let path = dir.join(format!("{page}.md"));
+                    path.is_file().then(|| path.to_string_lossy().into_owned())
+                }));
+            },
+            "--code-tag" => res.opts.code_tag = value(&arg)?,
+            "--math-tag" => res.opts.math_tag = value(&arg)?,
+            "--unconv-tag" => res.opts.unconverted_tag = value(&arg)?,
+            "--micro-level" => {
+                let level = value(&arg)?;
+                res.opts.micro_section_heading_level = level.parse()
+                    .map_err(|_| format!("invalid micro section level: {level}"))?;
+            },
+            "--html-blocks" => {
+                res.opts.html_blocks = match value(&arg)?.as_str() {
+                    "code" => HtmlBlockPolicy::Code,
+                    "map" => HtmlBlockPolicy::Map,
+                    "text" => HtmlBlockPolicy::Text,
+                    "drop" => HtmlBlockPolicy::Drop,
+                    mode => return Err(format!("invalid html block mode: {mode}")),
+                };
+            },
+            "--inline-html" => {
+                res.opts.inline_html = match value(&arg)?.as_str() {
+                    "enclose" => InlineHtmlPolicy::Enclose,
+                    "strip" => InlineHtmlPolicy::Strip,
+                    mode => return Err(format!("invalid inline html mode: {mode}")),
+                };
+            },
+            _ if arg.starts_with('-') && arg != "-" => return Err(format!("unknown option: {arg}")),
+            _ if res.input.is_some() => return Err(format!("unexpected argument: {arg}")),
+            _ => res.input = Some(arg),
+        }
+    }
+    Ok(Some(res))
+}
+
+fn read_input(input: Option<&str>) -> io::Result<String> {
+    let mut string = String::new();
+    match input {
+        None | Some("-") => { io::stdin().read_to_string(&mut string)?; },
+        Some(path) => string = fs::read_to_string(path)?,
+    }
+    Ok(string)
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            print!("{USAGE}");
+            return ExitCode::SUCCESS;
+        },
+        Err(err) => {
+            eprintln!("md-to-incodoc: {err}\n\n{USAGE}");
+            return ExitCode::from(EXIT_USAGE);
+        },
+    };
+
+    let input = match read_input(args.input.as_deref()) {
+        Ok(input) => input,
+        Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+            eprintln!("md-to-incodoc: input is not valid UTF-8");
+            return ExitCode::from(EXIT_CONVERSION);
+        },
+        Err(err) => {
+            eprintln!("md-to-incodoc: could not read input: {err}");
+            return ExitCode::from(EXIT_IO);
+        },
+    };
+
+    let output = if args.to_markdown {
+        match parse(&input) {
+            Ok(doc) => doc_to_md(&doc),
+            Err(err) => {
+                eprintln!("md-to-incodoc: could not parse incodoc: {err}");
+                return ExitCode::from(EXIT_CONVERSION);
+            },
+        }
+    } else {
+        match convert(&input, &args.opts) {
+            Ok((doc, diagnostics)) => {
+                if !args.quiet {
+                    for diagnostic in diagnostics {
+                        eprintln!("md-to-incodoc: warning: {diagnostic}");
+                    }
+                }
+                doc_to_incodoc(&doc)
+            },
+            Err(err) => {
+                eprintln!("md-to-incodoc: conversion failed: {err}");
+                return ExitCode::from(EXIT_CONVERSION);
+            },
+        }
+    };
+
+    let written = match &args.output {
+        Some(path) => fs::write(path, output),
+        None => io::stdout().write_all(output.as_bytes()),
+    };
+    if let Err(err) = written {
+        eprintln!("md-to-incodoc: could not write output: {err}");
+        return ExitCode::from(EXIT_IO);
+    }
+    ExitCode::SUCCESS
+}
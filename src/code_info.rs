@@ -0,0 +1,95 @@
+//! Fenced code block info strings.
+//!
+//! ```` ```rust,ignore title="main.rs" {1,3-5} ```` has language "rust", tag "ignore",
+//! prop "title" and prop "highlight" set to "1,3-5".
+//! Pandoc style attributes such as `{.class #id key=value}` give tags, an "id" prop and props;
+//! without a language the first class is the language.
+
+use crate::prop_values::{ typed_prop_val, unquote };
+
+use incodoc::*;
+
+/// Split on whitespace and commas outside of quotes; brace groups are returned separately.
+/// Quotes only open at the start of a word or a value, so "it's" is just a word.
+fn split_info(info: &str) -> (Vec<String>, Vec<String>) {
+    let mut words = Vec::new();
+    let mut groups = Vec::new();
+    let mut word = String::new();
+    let mut quote = None;
+    let mut in_group = false;
+    for c in info.chars() {
+        match (c, quote) {
+            (c, Some(q)) => {
+                word.push(c);
+                if c == q { quote = None; }
+            },
+            ('"' | '\'', None) if word.is_empty() || word.ends_with('=') => {
+                word.push(c);
+                quote = Some(c);
+            },
+            ('{', None) if !in_group => {
+                if !word.is_empty() { words.push(std::mem::take(&mut word)); }
+                in_group = true;
+            },
+            ('}', None) if in_group => {
+                groups.push(std::mem::take(&mut word));
+                in_group = false;
+            },
+            (c, None) if !in_group && (c.is_whitespace() || c == ',') => {
+                if !word.is_empty() { words.push(std::mem::take(&mut word)); }
+            },
+            _ => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        if in_group { groups.push(word); } else { words.push(word); }
+    }
+    (words, groups)
+}
+
+fn is_line_ranges(group: &str) -> bool {
+    !group.trim().is_empty()
+        && group.chars().all(|c| c.is_ascii_digit() || matches!(c, ',' | '-' | ' '))
+}
+
+fn insert_key_val(word: &str, code_block: &mut CodeBlock) -> bool {
+    let Some((key, val)) = word.split_once('=') else { return false; };
+    if key.is_empty() { return false; }
+    let (text, quoted) = unquote(val);
+    let val = if quoted { PropVal::String(text) } else { typed_prop_val(&text) };
+    code_block.props.insert(key.to_string(), val);
+    true
+}
+
+/// Fill in language, tags and props of the code block from the info string.
+pub(crate) fn parse_info_string(info: &str, code_block: &mut CodeBlock) {
+    let (words, groups) = split_info(info.trim());
+    for (i, word) in words.into_iter().enumerate() {
+        if insert_key_val(&word, code_block) { continue; }
+        if i == 0 {
+            code_block.language = word;
+        } else {
+            code_block.tags.insert(word);
+        }
+    }
+    for group in groups {
+        if is_line_ranges(&group) {
+            let ranges: Vec<_> = group.split([',', ' ']).filter(|r| !r.is_empty()).collect();
+            code_block.props.insert("highlight".to_string(), PropVal::String(ranges.join(",")));
+            continue;
+        }
+        for word in split_info(&group).0 {
+            if let Some(class) = word.strip_prefix('.') {
+                if code_block.language.is_empty() {
+                    code_block.language = class.to_string();
+                } else {
+                    code_block.tags.insert(class.to_string());
+                }
+            } else if let Some(id) = word.strip_prefix('#') {
+                code_block.props.insert("id".to_string(), PropVal::String(id.to_string()));
+            } else if !insert_key_val(&word, code_block) {
+                code_block.tags.insert(word);
+            }
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use std::{ fmt, error::Error, ops::Range };
+
+/// Something that was lost or approximated during conversion.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    /// Byte range in the markdown source.
+    pub range: Range<usize>,
+}
+
+/// What was lost or approximated.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DiagnosticKind {
+    /// A markdown construct that has no conversion and was dropped.
+    Unsupported(String),
+    /// Line in a metadata block that was not understood and was dropped.
+    MetadataLine(String),
+    /// Link to an anchor that no heading or section in the document has.
+    BrokenAnchor(String),
+    /// Wikilink to a page the resolver does not know.
+    UnresolvedWikilink(String),
+    /// Block quote or footnote definition nested too deep for a heading level,
+    /// kept as part of the section around it.
+    DeepNesting,
+    /// Footnote reference to a label that no footnote definition has.
+    DanglingFootnote(String),
+    /// Footnote definition that nothing references.
+    UnreferencedFootnote(String),
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}: ", self.range.start, self.range.end)?;
+        match &self.kind {
+            DiagnosticKind::Unsupported(name) => write!(f, "unsupported {name} dropped"),
+            DiagnosticKind::MetadataLine(line) => write!(f, "metadata line dropped: {line}"),
+            DiagnosticKind::BrokenAnchor(url) => write!(f, "link to missing anchor: {url}"),
+            DiagnosticKind::UnresolvedWikilink(page) => write!(f, "unresolved wikilink: {page}"),
+            DiagnosticKind::DeepNesting => write!(f, "nested too deep, merged into parent"),
+            DiagnosticKind::DanglingFootnote(label) => {
+                write!(f, "footnote reference without definition: {label}")
+            },
+            DiagnosticKind::UnreferencedFootnote(label) => {
+                write!(f, "footnote definition without reference: {label}")
+            },
+        }
+    }
+}
+
+/// Error that makes conversion fail.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ConvertError {
+    /// The end of a construct was found without a matching start.
+    Unbalanced {
+        construct: &'static str,
+        range: Range<usize>,
+    },
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConvertError::Unbalanced { construct, range } => write!(
+                f, "{}..{}: end of {construct} without start", range.start, range.end
+            ),
+        }
+    }
+}
+
+impl Error for ConvertError { }
@@ -0,0 +1,221 @@
+//! Gathering footnote definitions into a trailing section.
+//!
+//! Definitions are numbered in order of first reference, then unreferenced ones in order.
+//! References show the number and get an id, which the definitions link back to; references
+//! without definition keep their label.
+//! Also cross-checking references and definitions.
+
+use crate::{ Diagnostic, DiagnosticKind };
+
+use incodoc::{ *, actions::collect::links_mut_section };
+
+use std::{
+    collections::{ HashMap, HashSet },
+    mem,
+    ops::Range,
+};
+
+const DEF_ID_PREFIX: &str = "#footnote-";
+
+/// Label of a footnote definition, from its id.
+fn def_label(section: &Section) -> Option<&str> {
+    match section.props.get("id") {
+        Some(PropVal::String(id)) => id.strip_prefix(&DEF_ID_PREFIX[1..]),
+        _ => None,
+    }
+}
+
+/// Labels match case-insensitively, like the parser matches them.
+fn key(label: &str) -> String {
+    label.to_lowercase()
+}
+
+fn ref_label(link: &Link) -> Option<&str> {
+    if link.tags.contains("footnote-ref") {
+        link.url.strip_prefix(DEF_ID_PREFIX)
+    } else {
+        None
+    }
+}
+
+fn is_def(section: &Section) -> bool {
+    section.tags.contains("footnote-def")
+}
+
+/// Remove the footnote definitions from the items, in document order.
+fn take_defs(items: &mut Vec<SectionItem>, defs: &mut Vec<Section>) {
+    for item in mem::take(items) {
+        match item {
+            SectionItem::Section(section) if is_def(&section) => defs.push(section),
+            SectionItem::Section(mut section) => {
+                take_defs(&mut section.items, defs);
+                items.push(SectionItem::Section(section));
+            },
+            item => items.push(item),
+        }
+    }
+}
+
+/// Raise the heading levels so the section fits under a parent of the given level.
+fn relevel(section: &mut Section, parent_level: u8) {
+    section.heading.level = parent_level.saturating_add(1);
+    for item in &mut section.items {
+        if let SectionItem::Section(sub) = item {
+            relevel(sub, section.heading.level);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Numbering {
+    /// Labels with a definition; references to other labels are left alone.
+    defined: HashSet<String>,
+    numbers: HashMap<String, usize>,
+    /// Ids of the references to each label, in order.
+    refs: HashMap<String, Vec<String>>,
+}
+
+impl Numbering {
+    fn number(&mut self, label: &str) -> usize {
+        let next = self.numbers.len() + 1;
+        *self.numbers.entry(label.to_string()).or_insert(next)
+    }
+
+    fn reference(&mut self, link: &mut Link) {
+        let Some(label) = ref_label(link).map(key) else {
+            return;
+        };
+        if !self.defined.contains(&label) {
+            return;
+        }
+        let number = self.number(&label);
+        let refs = self.refs.entry(label.clone()).or_default();
+        let id = format!("footnote-ref-{label}-{}", refs.len() + 1);
+        refs.push(id.clone());
+        link.props.insert("id".to_string(), PropVal::String(id));
+        link.items = vec![EmOrText::Text(number.to_string())];
+    }
+}
+
+/// Move all footnote definitions into a section tagged "footnotes" at the end of the document.
+pub(crate) fn collect_footnotes(doc: &mut Doc, level: u8) {
+    let mut defs = Vec::new();
+    for item in mem::take(&mut doc.items) {
+        match item {
+            DocItem::Section(section) if is_def(&section) => defs.push(section),
+            DocItem::Section(mut section) => {
+                take_defs(&mut section.items, &mut defs);
+                doc.items.push(DocItem::Section(section));
+            },
+            item => doc.items.push(item),
+        }
+    }
+    if defs.is_empty() {
+        return;
+    }
+    let mut numbering = Numbering {
+        defined: defs.iter().filter_map(def_label).map(key).collect(),
+        ..Default::default()
+    };
+    for link in doc.links_mut(false) {
+        numbering.reference(link);
+    }
+    for def in &mut defs {
+        let mut links = Vec::new();
+        links_mut_section(def, &mut links);
+        for link in links {
+            numbering.reference(link);
+        }
+    }
+    for def in &defs {
+        if let Some(label) = def_label(def) {
+            numbering.number(&key(label));
+        }
+    }
+    defs.sort_by_key(|def| {
+        def_label(def).map_or(usize::MAX, |label| numbering.numbers[&key(label)])
+    });
+    let mut notes = Section {
+        heading: Heading {
+            level,
+            items: vec![EmOrText::Text("Footnotes".to_string())],
+            ..Default::default()
+        },
+        tags: Tags::from(["footnotes".to_string()]),
+        ..Default::default()
+    };
+    for mut def in defs {
+        relevel(&mut def, level);
+        if let Some(label) = def_label(&def).map(key) {
+            def.heading.items = vec![EmOrText::Text(numbering.numbers[&label].to_string())];
+            let backrefs = numbering.refs.get(&label).map(Vec::as_slice).unwrap_or_default();
+            if !backrefs.is_empty() {
+                let mut par = Paragraph::default();
+                for (i, id) in backrefs.iter().enumerate() {
+                    if i > 0 {
+                        par.items.push(ParagraphItem::Text(" ".to_string()));
+                    }
+                    par.items.push(ParagraphItem::Link(Link {
+                        url: format!("#{id}"),
+                        items: vec![EmOrText::Text("↩".to_string())],
+                        tags: Tags::from(["footnote-backref".to_string()]),
+                        ..Default::default()
+                    }));
+                }
+                def.items.push(SectionItem::Paragraph(par));
+            }
+        }
+        notes.items.push(SectionItem::Section(def));
+    }
+    doc.items.push(DocItem::Section(notes));
+}
+
+fn tag_unreferenced(section: &mut Section, referenced: &HashSet<String>) {
+    if def_label(section).is_some_and(|label| !referenced.contains(&key(label))) {
+        section.tags.insert("unreferenced-footnote".to_string());
+    }
+    for item in &mut section.items {
+        if let SectionItem::Section(sub) = item {
+            tag_unreferenced(sub, referenced);
+        }
+    }
+}
+
+/// Tag references without definition "dangling-footnote-ref" and definitions without
+/// reference "unreferenced-footnote", and report both.
+/// `refs` and `defs` are the labels and source ranges of all references and definitions.
+pub(crate) fn check_footnotes(
+    doc: &mut Doc,
+    refs: &[(String, Range<usize>)],
+    defs: &[(String, Range<usize>)],
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let referenced: HashSet<_> = refs.iter().map(|(label, _)| key(label)).collect();
+    let defined: HashSet<_> = defs.iter().map(|(label, _)| key(label)).collect();
+    for (label, range) in refs {
+        if !defined.contains(&key(label)) {
+            diagnostics.push(Diagnostic {
+                kind: DiagnosticKind::DanglingFootnote(label.clone()),
+                range: range.clone(),
+            });
+        }
+    }
+    for (label, range) in defs {
+        if !referenced.contains(&key(label)) {
+            diagnostics.push(Diagnostic {
+                kind: DiagnosticKind::UnreferencedFootnote(label.clone()),
+                range: range.clone(),
+            });
+        }
+    }
+    for link in doc.links_mut(false) {
+        if ref_label(link).is_some_and(|label| !defined.contains(&key(label))) {
+            link.tags.insert("dangling-footnote-ref".to_string());
+        }
+    }
+    for item in &mut doc.items {
+        if let DocItem::Section(section) = item {
+            tag_unreferenced(section, &referenced);
+        }
+    }
+}
@@ -0,0 +1,374 @@
+//! YAML front matter.
+//!
+//! Only the subset of YAML used for front matter is understood: block mappings and sequences,
+//! flow sequences and mappings, quoted and plain scalars and literal (`|`) and folded (`>`)
+//! block scalars. Anchors, aliases, tags and multi-line flow collections are not.
+//!
+//! Keys are mapped onto the document like this:
+//!
+//! - `tags`, `keywords`, `categories`: a sequence, or a comma or space separated scalar,
+//!   to document tags
+//! - `nav`: mapping or sequence of single entry mappings to a document nav;
+//!   an entry with a scalar value is a link from label to url,
+//!   an entry with a mapping or sequence value is a sub nav with the key as description
+//! - `title`: string prop
+//! - other scalars: prop; an integer to an int prop, a `YYYY-MM-DD` date (optionally followed
+//!   by a time, which is discarded) to a date prop, anything else to a string prop;
+//!   quoted scalars are always string props and null values are skipped
+//! - nested mappings: flattened with dotted keys, `author: { name: a }` to prop `author.name`
+//! - other sequences: flattened with indices, `aliases: [a, b]` to props `aliases.0` and
+//!   `aliases.1`
+
+use crate::{ Diagnostic, DiagnosticKind };
+use crate::prop_values::{ typed_prop_val, unquote };
+
+use incodoc::*;
+
+use std::ops::Range;
+
+const TAG_KEYS: [&str; 3] = ["tags", "keywords", "categories"];
+const STRING_KEYS: [&str; 1] = ["title"];
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Yaml {
+    Null,
+    Scalar { text: String, quoted: bool },
+    Seq(Vec<Yaml>),
+    Map(Vec<(String, Yaml)>),
+}
+
+#[derive(Clone, Debug)]
+struct Line {
+    indent: usize,
+    text: String,
+    range: Range<usize>,
+}
+
+pub(crate) fn parse_yaml_block(
+    raw: &str, offset: usize, doc: &mut Doc, diagnostics: &mut Vec<Diagnostic>
+) {
+    let mut lines = Vec::new();
+    let mut line_start = offset;
+    for full_line in raw.split_inclusive('\n') {
+        let text = full_line.trim();
+        let indent = full_line.len() - full_line.trim_start().len();
+        let range = line_start + indent..line_start + full_line.trim_end().len();
+        line_start += full_line.len();
+        lines.push(Line { indent, text: text.to_string(), range });
+    }
+    // parsing shifts mapping items that start on a sequence line, report the lines as written
+    let original = lines.clone();
+    let mut pos = 0;
+    let mut items = Vec::new();
+    while pos < lines.len() {
+        skip_blank(&lines, &mut pos);
+        if pos >= lines.len() { break; }
+        let indent = lines[pos].indent;
+        let before = pos;
+        let reported = diagnostics.len();
+        match parse_block(&mut lines, &mut pos, indent, diagnostics) {
+            Yaml::Map(map) if pos > before => items.extend(map),
+            _ => {
+                // everything it took is dropped, not just what was not understood inside it
+                diagnostics.truncate(reported);
+                pos = pos.max(before + 1);
+                original[before..pos].iter()
+                    .filter(|line| !line.text.is_empty() && !line.text.starts_with('#'))
+                    .for_each(|line| unknown_line(line, diagnostics));
+            },
+        }
+    }
+    yaml_to_doc(items, doc);
+}
+
+fn unknown_line(line: &Line, diagnostics: &mut Vec<Diagnostic>) {
+    diagnostics.push(Diagnostic {
+        kind: DiagnosticKind::MetadataLine(line.text.clone()),
+        range: line.range.clone(),
+    });
+}
+
+/// Skip blank and comment lines.
+fn skip_blank(lines: &[Line], pos: &mut usize) {
+    while let Some(line) = lines.get(*pos)
+        && (line.text.is_empty() || line.text.starts_with('#'))
+    {
+        *pos += 1;
+    }
+}
+
+fn is_seq_item(text: &str) -> bool {
+    text == "-" || text.starts_with("- ")
+}
+
+fn parse_block(
+    lines: &mut [Line], pos: &mut usize, indent: usize, diagnostics: &mut Vec<Diagnostic>
+) -> Yaml {
+    skip_blank(lines, pos);
+    if *pos < lines.len() && is_seq_item(&lines[*pos].text) {
+        parse_seq(lines, pos, indent, diagnostics)
+    } else {
+        parse_map(lines, pos, indent, diagnostics)
+    }
+}
+
+fn parse_seq(
+    lines: &mut [Line], pos: &mut usize, indent: usize, diagnostics: &mut Vec<Diagnostic>
+) -> Yaml {
+    let mut items = Vec::new();
+    loop {
+        skip_blank(lines, pos);
+        if *pos >= lines.len() { break; }
+        let line = &lines[*pos];
+        if line.indent != indent || !is_seq_item(&line.text) { break; }
+        let rest = line.text[1..].trim_start().to_string();
+        if rest.is_empty() {
+            *pos += 1;
+            items.push(parse_nested(lines, pos, indent, diagnostics));
+        } else if split_key(&rest).is_some() {
+            // mapping that starts on the line of the item: continue as if it was indented
+            let line = &mut lines[*pos];
+            let shift = line.text.len() - rest.len();
+            line.indent += shift;
+            line.range.start += shift;
+            line.text = rest;
+            let map_indent = line.indent;
+            items.push(parse_map(lines, pos, map_indent, diagnostics));
+        } else {
+            items.push(parse_inline(&rest));
+            *pos += 1;
+        }
+    }
+    Yaml::Seq(items)
+}
+
+fn parse_map(
+    lines: &mut [Line], pos: &mut usize, indent: usize, diagnostics: &mut Vec<Diagnostic>
+) -> Yaml {
+    let mut items = Vec::new();
+    loop {
+        skip_blank(lines, pos);
+        if *pos >= lines.len() { break; }
+        let line = &lines[*pos];
+        if line.indent < indent || is_seq_item(&line.text) { break; }
+        if line.indent > indent {
+            unknown_line(line, diagnostics);
+            *pos += 1;
+            continue;
+        }
+        let Some((key, rest)) = split_key(&line.text) else {
+            unknown_line(line, diagnostics);
+            *pos += 1;
+            continue;
+        };
+        let (key, rest) = (unquote(key).0, strip_comment(rest).to_string());
+        *pos += 1;
+        let value = if rest.is_empty() {
+            parse_nested(lines, pos, indent, diagnostics)
+        } else if rest.starts_with('|') || rest.starts_with('>') {
+            parse_block_scalar(lines, pos, indent, rest.starts_with('|'))
+        } else {
+            parse_inline(&rest)
+        };
+        items.push((key, value));
+    }
+    Yaml::Map(items)
+}
+
+/// Value on the lines after a key or sequence item without inline value.
+fn parse_nested(
+    lines: &mut [Line], pos: &mut usize, indent: usize, diagnostics: &mut Vec<Diagnostic>
+) -> Yaml {
+    skip_blank(lines, pos);
+    match lines.get(*pos) {
+        Some(next) if next.indent > indent => {
+            let next_indent = next.indent;
+            parse_block(lines, pos, next_indent, diagnostics)
+        },
+        // sequences may be at the same indentation as their key
+        Some(next) if next.indent == indent && is_seq_item(&next.text) => {
+            parse_seq(lines, pos, indent, diagnostics)
+        },
+        _ => Yaml::Null,
+    }
+}
+
+fn parse_block_scalar(lines: &[Line], pos: &mut usize, indent: usize, literal: bool) -> Yaml {
+    let mut parts = Vec::new();
+    let mut base = None;
+    while let Some(line) = lines.get(*pos)
+        && (line.text.is_empty() || line.indent > indent)
+    {
+        if line.text.is_empty() {
+            parts.push(String::new());
+        } else {
+            // literal blocks keep indentation relative to their first line
+            let base = *base.get_or_insert(line.indent);
+            let extra = if literal { line.indent.saturating_sub(base) } else { 0 };
+            parts.push(format!("{}{}", " ".repeat(extra), line.text));
+        }
+        *pos += 1;
+    }
+    while parts.last().is_some_and(String::is_empty) {
+        parts.pop();
+    }
+    let text = parts.join(if literal { "\n" } else { " " });
+    Yaml::Scalar { text, quoted: true }
+}
+
+/// Split `key: value` into key and value, ignoring colons in quotes and urls.
+fn split_key(text: &str) -> Option<(&str, &str)> {
+    let mut quote = None;
+    for (i, c) in text.char_indices() {
+        match (c, quote) {
+            ('"' | '\'', None) if i == 0 => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            (':', None) => {
+                let rest = &text[i + 1..];
+                if rest.is_empty() || rest.starts_with(' ') {
+                    return Some((text[..i].trim(), rest.trim()));
+                }
+            },
+            _ => { },
+        }
+    }
+    None
+}
+
+fn strip_comment(text: &str) -> &str {
+    if text.starts_with('"') || text.starts_with('\'') {
+        return text;
+    }
+    match text.find(" #") {
+        Some(i) => text[..i].trim_end(),
+        None => text,
+    }
+}
+
+/// Split on commas outside of quotes and brackets.
+fn split_flow(text: &str) -> Vec<&str> {
+    let mut res = Vec::new();
+    let mut depth = 0;
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('[' | '{', None) => depth += 1,
+            (']' | '}', None) => depth -= 1,
+            (',', None) if depth == 0 => {
+                res.push(text[start..i].trim());
+                start = i + 1;
+            },
+            _ => { },
+        }
+    }
+    let last = text[start..].trim();
+    if !last.is_empty() {
+        res.push(last);
+    }
+    res
+}
+
+fn parse_inline(text: &str) -> Yaml {
+    let text = strip_comment(text.trim());
+    if let Some(inner) = text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+        Yaml::Seq(split_flow(inner).into_iter().map(parse_inline).collect())
+    } else if let Some(inner) = text.strip_prefix('{').and_then(|t| t.strip_suffix('}')) {
+        let items = split_flow(inner).into_iter().filter_map(|entry| {
+            let (key, value) = split_key(entry)?;
+            Some((unquote(key).0, parse_inline(value)))
+        });
+        Yaml::Map(items.collect())
+    } else if matches!(text, "" | "~" | "null" | "Null" | "NULL") {
+        Yaml::Null
+    } else {
+        let (text, quoted) = unquote(text);
+        Yaml::Scalar { text, quoted }
+    }
+}
+
+fn yaml_to_doc(items: Vec<(String, Yaml)>, doc: &mut Doc) {
+    for (key, value) in items {
+        if TAG_KEYS.contains(&key.as_str()) {
+            insert_tags(value, &mut doc.tags);
+        } else if key == "nav" {
+            let mut nav = Nav::default();
+            fill_nav(value, &mut nav);
+            doc.navs.push(nav);
+        } else if STRING_KEYS.contains(&key.as_str())
+            && let Yaml::Scalar { text, .. } = value
+        {
+            doc.props.insert(key, PropVal::String(text));
+        } else {
+            insert_props(key, value, &mut doc.props);
+        }
+    }
+}
+
+fn insert_tags(value: Yaml, tags: &mut Tags) {
+    match value {
+        Yaml::Scalar { text, .. } => {
+            let sep = if text.contains(',') { ',' } else { ' ' };
+            tags.extend(
+                text.split(sep).map(str::trim).filter(|t| !t.is_empty()).map(str::to_string)
+            );
+        },
+        Yaml::Seq(items) => {
+            for item in items {
+                if let Yaml::Scalar { text, .. } = item {
+                    tags.insert(text);
+                }
+            }
+        },
+        Yaml::Null | Yaml::Map(_) => { },
+    }
+}
+
+fn insert_props(key: String, value: Yaml, props: &mut Props) {
+    match value {
+        Yaml::Null => { },
+        Yaml::Scalar { text, quoted: true } => { props.insert(key, PropVal::String(text)); },
+        Yaml::Scalar { text, quoted: false } => { props.insert(key, typed_prop_val(&text)); },
+        Yaml::Seq(items) => {
+            for (i, item) in items.into_iter().enumerate() {
+                insert_props(format!("{key}.{i}"), item, props);
+            }
+        },
+        Yaml::Map(items) => {
+            for (sub, item) in items {
+                insert_props(format!("{key}.{sub}"), item, props);
+            }
+        },
+    }
+}
+
+fn fill_nav(value: Yaml, nav: &mut Nav) {
+    let entries = match value {
+        Yaml::Map(items) => items,
+        Yaml::Seq(items) => items.into_iter()
+            .filter_map(|item| match item {
+                Yaml::Map(map) => Some(map),
+                _ => None,
+            })
+            .flatten()
+            .collect(),
+        Yaml::Null | Yaml::Scalar { .. } => Vec::new(),
+    };
+    for (label, value) in entries {
+        match value {
+            Yaml::Scalar { text, .. } => nav.links.push(Link {
+                url: text,
+                items: vec![EmOrText::Text(label)],
+                ..Default::default()
+            }),
+            Yaml::Map(_) | Yaml::Seq(_) => {
+                let mut sub = Nav { description: label, ..Default::default() };
+                fill_nav(value, &mut sub);
+                nav.subs.push(sub);
+            },
+            Yaml::Null => { },
+        }
+    }
+}
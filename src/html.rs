@@ -0,0 +1,433 @@
+//! Mapping of a safe subset of html to incodoc.
+//!
+//! `<details>` opens a section headed by its `<summary>`, `<p>` is a paragraph, `<table>` a table,
+//! `<img>` an image link, `<a>` a link, `<kbd>` text tagged "kbd" and `<br>` a line break.
+//! Anything else, including unbalanced tags, is not mapped so the caller can fall back.
+//! `href` and `src` with a `javascript:`, `vbscript:` or non-image `data:` url are dropped.
+
+use crate::images;
+
+use incodoc::*;
+
+use std::{ iter::Peekable, vec::IntoIter };
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Token {
+    Open { name: String, attrs: Vec<(String, String)> },
+    Close(String),
+    Text(String),
+}
+
+/// Elements that have no content and no closing tag.
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source",
+    "track", "wbr",
+];
+
+pub(crate) fn is_void(name: &str) -> bool {
+    VOID_ELEMENTS.contains(&name)
+}
+
+pub(crate) fn attr<'a>(attrs: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    attrs.iter().find(|(attr, _)| attr == name).map(|(_, val)| val.as_str())
+}
+
+/// An url attribute, dropped if its scheme runs script or embeds a document.
+/// Browsers ignore whitespace and control characters in the scheme, so this does too;
+/// `data:` urls are kept for image sources only.
+pub(crate) fn url_attr<'a>(attrs: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    let url = attr(attrs, name)?;
+    let Some((scheme, rest)) = url.split_once(':') else { return Some(url); };
+    let scheme: String = scheme.chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_ascii_control())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    match scheme.as_str() {
+        "javascript" | "vbscript" => None,
+        "data" if name != "src" || !rest.trim_start().to_ascii_lowercase().starts_with("image/")
+            => None,
+        _ => Some(url),
+    }
+}
+
+/// Html split into tags and text, with character references decoded.
+/// Returns `None` for comments, declarations and tags that do not parse.
+pub(crate) fn tokens(html: &str) -> Option<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = html;
+    while !rest.is_empty() {
+        let Some(start) = rest.find('<') else {
+            tokens.push(Token::Text(decode(rest)));
+            break;
+        };
+        if start > 0 {
+            tokens.push(Token::Text(decode(&rest[..start])));
+        }
+        let (token, len) = tag(&rest[start..])?;
+        tokens.push(token);
+        rest = &rest[start + len..];
+    }
+    Some(tokens)
+}
+
+/// Tag at the start of the input and its length in bytes.
+fn tag(input: &str) -> Option<(Token, usize)> {
+    let body = input.strip_prefix('<')?;
+    let (closing, body) = match body.strip_prefix('/') {
+        Some(body) => (true, body),
+        None => (false, body),
+    };
+    let name_len = body.find(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+        .unwrap_or(body.len());
+    if name_len == 0 || !body.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let name = body[..name_len].to_ascii_lowercase();
+    let mut rest = &body[name_len..];
+    let mut attrs = Vec::new();
+    loop {
+        rest = rest.trim_start();
+        if let Some(after) = rest.strip_prefix('>') {
+            rest = after;
+            break;
+        }
+        if let Some(after) = rest.strip_prefix("/>") {
+            rest = after;
+            break;
+        }
+        if closing {
+            return None;
+        }
+        let attr_len = rest.find(|c: char| c.is_whitespace() || "=>/\"'<".contains(c))
+            .unwrap_or(rest.len());
+        if attr_len == 0 {
+            return None;
+        }
+        let attr = rest[..attr_len].to_ascii_lowercase();
+        rest = rest[attr_len..].trim_start();
+        let mut val = String::new();
+        if let Some(after) = rest.strip_prefix('=') {
+            rest = after.trim_start();
+            let (raw, after) = match rest.chars().next()? {
+                q @ ('"' | '\'') => {
+                    let end = rest[1..].find(q)? + 1;
+                    (&rest[1..end], &rest[end + 1..])
+                },
+                _ => {
+                    let end = rest.find(|c: char| c.is_whitespace() || c == '>')
+                        .unwrap_or(rest.len());
+                    (&rest[..end], &rest[end..])
+                },
+            };
+            val = decode(raw);
+            rest = after;
+        }
+        attrs.push((attr, val));
+    }
+    let token = if closing { Token::Close(name) } else { Token::Open { name, attrs } };
+    Some((token, input.len() - rest.len()))
+}
+
+/// Decode the common named and all numeric character references; others are kept as is.
+pub(crate) fn decode(text: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let reference = rest.find(';')
+            .filter(|end| *end <= 32)
+            .and_then(|end| Some((reference(&rest[1..end])?, end)));
+        match reference {
+            Some((c, end)) => {
+                decoded.push(c);
+                rest = &rest[end + 1..];
+            },
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            },
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn reference(name: &str) -> Option<char> {
+    if let Some(num) = name.strip_prefix('#') {
+        let code = match num.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => num.parse().ok()?,
+        };
+        return Some(char::from_u32(code).filter(|c| *c != '\0').unwrap_or('\u{FFFD}'));
+    }
+    Some(match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{A0}',
+        "copy" => '©',
+        "reg" => '®',
+        "trade" => '™',
+        "hellip" => '…',
+        "mdash" => '—',
+        "ndash" => '–',
+        "middot" => '·',
+        _ => return None,
+    })
+}
+
+/// What an html block maps to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum BlockItem {
+    /// Start of a section, with the summary as heading if there is one.
+    DetailsOpen { summary: Vec<EmOrText>, open: bool },
+    DetailsClose,
+    Paragraph(Paragraph),
+}
+
+/// Map an html block, or `None` if it has anything outside of the subset.
+/// Details may be left open or closed in another block; other tags must be balanced.
+pub(crate) fn map_block(html: &str) -> Option<Vec<BlockItem>> {
+    let mut tokens = tokens(html)?.into_iter().peekable();
+    let mut items = Vec::new();
+    while let Some(token) = tokens.peek() {
+        match token {
+            Token::Text(text) if text.trim().is_empty() => {
+                tokens.next();
+            },
+            Token::Open { name, attrs } if name == "details" => {
+                let open = attr(attrs, "open").is_some();
+                items.push(BlockItem::DetailsOpen { summary: Vec::new(), open });
+                tokens.next();
+            },
+            Token::Close(name) if name == "details" => {
+                items.push(BlockItem::DetailsClose);
+                tokens.next();
+            },
+            Token::Open { name, .. } if name == "summary" => {
+                tokens.next();
+                let Some(BlockItem::DetailsOpen { summary, .. }) = items.last_mut() else {
+                    return None;
+                };
+                if !summary.is_empty() {
+                    return None;
+                }
+                let text = inline_text(&inline(&mut tokens, Some("summary"))?);
+                summary.push(EmOrText::Text(text));
+            },
+            Token::Open { name, attrs } if name == "p" => {
+                let mut par = Paragraph::default();
+                if let Some(align) = attr(attrs, "align") {
+                    par.props.insert("align".to_string(), PropVal::String(align.to_string()));
+                }
+                tokens.next();
+                par.items = inline(&mut tokens, Some("p"))?;
+                items.push(BlockItem::Paragraph(par));
+            },
+            Token::Open { name, .. } if name == "table" => {
+                tokens.next();
+                let table = table(&mut tokens)?;
+                items.push(BlockItem::Paragraph(Paragraph {
+                    items: vec![ParagraphItem::Table(table)],
+                    ..Default::default()
+                }));
+            },
+            _ => {
+                // stops without progress at tags outside of the subset
+                let par_items = inline(&mut tokens, None)?;
+                if par_items.is_empty() {
+                    return None;
+                }
+                items.push(BlockItem::Paragraph(Paragraph {
+                    items: par_items,
+                    ..Default::default()
+                }));
+            },
+        }
+    }
+    Some(items)
+}
+
+/// Inline content up to the closing tag, or without one up to the next block level tag.
+fn inline(
+    tokens: &mut Peekable<IntoIter<Token>>, until: Option<&str>
+) -> Option<Vec<ParagraphItem>> {
+    let mut items = Vec::new();
+    loop {
+        match tokens.peek() {
+            None if until.is_none() => break,
+            None => return None,
+            Some(Token::Close(name)) if Some(name.as_str()) == until => {
+                tokens.next();
+                break;
+            },
+            Some(Token::Open { name, .. }) if until.is_none()
+                && !matches!(name.as_str(), "a" | "img" | "kbd" | "br") => break,
+            Some(Token::Close(_)) if until.is_none() => break,
+            _ => { },
+        }
+        match tokens.next()? {
+            Token::Text(text) => items.push(ParagraphItem::Text(collapse_whitespace(&text))),
+            Token::Open { name, attrs } => match name.as_str() {
+                "br" => items.push(ParagraphItem::Text("\n".to_string())),
+                "img" => items.push(ParagraphItem::Link(image(&attrs))),
+                "a" => {
+                    let mut link = Link {
+                        url: url_attr(&attrs, "href").unwrap_or_default().to_string(),
+                        ..Default::default()
+                    };
+                    if let Some(title) = attr(&attrs, "title") {
+                        link.props.insert("title".to_string(), PropVal::String(title.to_string()));
+                    }
+                    for item in inline(tokens, Some("a"))? {
+                        match item {
+                            ParagraphItem::Link(image) if image.tags.contains("image") => {
+                                link.props.insert("image".to_string(), PropVal::String(image.url));
+                                link.items.extend(image.items);
+                            },
+                            item => link.items.push(EmOrText::Text(inline_text(&[item]))),
+                        }
+                    }
+                    items.push(ParagraphItem::Link(link));
+                },
+                "kbd" => {
+                    let text = inline_text(&inline(tokens, Some("kbd"))?);
+                    items.push(ParagraphItem::MText(TextWithMeta {
+                        text,
+                        tags: Tags::from(["kbd".to_string()]),
+                        ..Default::default()
+                    }));
+                },
+                _ => return None,
+            },
+            Token::Close(_) => return None,
+        }
+    }
+    Some(tidy(items))
+}
+
+pub(crate) fn image(attrs: &[(String, String)]) -> Link {
+    let mut link = Link {
+        url: url_attr(attrs, "src").unwrap_or_default().to_string(),
+        tags: Tags::from(["image".to_string()]),
+        ..Default::default()
+    };
+    if let Some(alt) = attr(attrs, "alt").filter(|alt| !alt.is_empty()) {
+        link.items.push(EmOrText::Text(alt.to_string()));
+    }
+    if let Some(title) = attr(attrs, "title") {
+        link.props.insert("title".to_string(), PropVal::String(title.to_string()));
+    }
+    images::add_alt(&mut link);
+    images::add_html_dimensions(&mut link, attrs);
+    link
+}
+
+fn table(tokens: &mut Peekable<IntoIter<Token>>) -> Option<Table> {
+    let mut table = Table::default();
+    let mut row: Option<TableRow> = None;
+    loop {
+        match tokens.next()? {
+            Token::Text(text) if text.trim().is_empty() => { },
+            Token::Close(name) if name == "table" => break,
+            Token::Open { name, .. } | Token::Close(name)
+                if matches!(name.as_str(), "thead" | "tbody" | "tfoot") => { },
+            Token::Open { name, .. } if name == "tr" && row.is_none() => {
+                row = Some(TableRow { is_header: true, ..Default::default() });
+            },
+            Token::Close(name) if name == "tr" => {
+                let mut row = row.take()?;
+                row.is_header &= !row.items.is_empty();
+                table.rows.push(row);
+            },
+            Token::Open { name, attrs } if name == "th" || name == "td" => {
+                let row = row.as_mut()?;
+                let mut cell = Paragraph {
+                    items: inline(tokens, Some(&name))?,
+                    ..Default::default()
+                };
+                if let Some(align) = attr(&attrs, "align") {
+                    cell.props.insert("align".to_string(), PropVal::String(align.to_string()));
+                }
+                row.is_header &= name == "th";
+                row.items.push(cell);
+            },
+            _ => return None,
+        }
+    }
+    row.is_none().then_some(table)
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    let mut collapsed = String::new();
+    for (i, word) in text.split_ascii_whitespace().enumerate() {
+        if i > 0 {
+            collapsed.push(' ');
+        }
+        collapsed.push_str(word);
+    }
+    if text.starts_with(|c: char| c.is_ascii_whitespace()) && !collapsed.is_empty() {
+        collapsed.insert(0, ' ');
+    }
+    if text.ends_with(|c: char| c.is_ascii_whitespace()) {
+        collapsed.push(' ');
+    }
+    collapsed
+}
+
+/// Merge adjacent text and trim whitespace at the ends and around line breaks.
+fn tidy(items: Vec<ParagraphItem>) -> Vec<ParagraphItem> {
+    let mut tidied: Vec<ParagraphItem> = Vec::new();
+    for item in items {
+        match (item, tidied.last_mut()) {
+            (ParagraphItem::Text(text), Some(ParagraphItem::Text(last))) => {
+                if last.ends_with(' ') && text.starts_with(' ') {
+                    last.push_str(&text[1..]);
+                } else {
+                    last.push_str(&text);
+                }
+            },
+            (item, _) => tidied.push(item),
+        }
+    }
+    let last = tidied.len().saturating_sub(1);
+    for (i, item) in tidied.iter_mut().enumerate() {
+        if let ParagraphItem::Text(text) = item {
+            let mut trimmed = text.replace(" \n", "\n").replace("\n ", "\n");
+            if i == 0 {
+                trimmed = trimmed.trim_start_matches(' ').to_string();
+            }
+            if i == last {
+                trimmed = trimmed.trim_end_matches(' ').to_string();
+            }
+            *text = trimmed;
+        }
+    }
+    tidied.retain(|item| !matches!(item, ParagraphItem::Text(text) if text.is_empty()));
+    tidied
+}
+
+/// Plain text of inline items.
+pub(crate) fn inline_text(items: &[ParagraphItem]) -> String {
+    let mut text = String::new();
+    for item in items {
+        match item {
+            ParagraphItem::Text(t) => text.push_str(t),
+            ParagraphItem::MText(mtext) => text.push_str(&mtext.text),
+            ParagraphItem::Em(em) => text.push_str(&em.text),
+            ParagraphItem::Link(link) => {
+                for item in &link.items {
+                    match item {
+                        EmOrText::Text(t) => text.push_str(t),
+                        EmOrText::Em(em) => text.push_str(&em.text),
+                    }
+                }
+            },
+            ParagraphItem::Code(_) | ParagraphItem::List(_) | ParagraphItem::Table(_) => { },
+        }
+    }
+    text
+}
@@ -0,0 +1,168 @@
+//! Image props and figures.
+//!
+//! Images get their alt text as "alt" prop and their dimensions as "width" and "height" props,
+//! from pandoc style attributes after the image (`![a](p.png){width=50%}`) or a `=WxH` suffix of
+//! the destination (`![a](<p.png =100x50>)`); other attributes become props as well.
+//! A paragraph with just an image is tagged "figure"; a paragraph of just light emphasis right
+//! after it becomes its "caption" prop.
+
+use crate::prop_values::typed_prop_val;
+
+use incodoc::*;
+
+const DIMENSIONS: [&str; 2] = ["width", "height"];
+
+fn dimension(props: &mut Props, key: &str, val: &str) {
+    if !val.is_empty() {
+        props.insert(key.to_string(), typed_prop_val(val));
+    }
+}
+
+/// Set the "alt" prop to the plain alt text.
+pub(crate) fn add_alt(image: &mut Link) {
+    let alt: String = image.items.iter().map(|item| match item {
+        EmOrText::Text(text) => text.as_str(),
+        EmOrText::Em(em) => em.text.as_str(),
+    }).collect();
+    if !alt.is_empty() {
+        image.props.insert("alt".to_string(), PropVal::String(alt));
+    }
+}
+
+/// Take the dimensions from a `=WxH` suffix of the url, either of which may be left out.
+pub(crate) fn add_url_dimensions(image: &mut Link) {
+    let Some((url, suffix)) = image.url.rsplit_once(' ') else { return; };
+    let Some((width, height)) = suffix.strip_prefix('=').and_then(|size| size.split_once('x'))
+    else {
+        return;
+    };
+    if width.is_empty() && height.is_empty() {
+        return;
+    }
+    dimension(&mut image.props, "width", width);
+    dimension(&mut image.props, "height", height);
+    image.url = url.trim_end().to_string();
+}
+
+/// Set the dimensions from html attributes.
+pub(crate) fn add_html_dimensions(image: &mut Link, attrs: &[(String, String)]) {
+    for (key, val) in attrs {
+        if DIMENSIONS.contains(&key.as_str()) {
+            dimension(&mut image.props, key, val);
+        }
+    }
+}
+
+/// Props of pandoc style attributes at the start of the source and their length: "id" from
+/// `#id`, "class" from `.class` (space separated) and typed props from `key=value`.
+/// Attributes with other words or over more than one line are left as text.
+pub(crate) fn attributes(source: &str) -> Option<(Props, usize)> {
+    let end = source.strip_prefix('{')?.find('}')? + 1;
+    let mut rest = &source[1..end];
+    if rest.contains('\n') {
+        return None;
+    }
+    let mut props = Props::new();
+    let mut classes = Vec::new();
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        let key_end = rest.find(|c: char| c == '=' || c.is_whitespace()).unwrap_or(rest.len());
+        let key = &rest[..key_end];
+        rest = &rest[key_end..];
+        let Some(val) = rest.strip_prefix('=') else {
+            if let Some(class) = key.strip_prefix('.').filter(|class| !class.is_empty()) {
+                classes.push(class);
+            } else if let Some(id) = key.strip_prefix('#').filter(|id| !id.is_empty()) {
+                props.insert("id".to_string(), PropVal::String(id.to_string()));
+            } else {
+                return None;
+            }
+            continue;
+        };
+        if key.is_empty() {
+            return None;
+        }
+        let (val, after) = match val.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"')?,
+            None => val.split_at(val.find(char::is_whitespace).unwrap_or(val.len())),
+        };
+        rest = after;
+        if DIMENSIONS.contains(&key) {
+            dimension(&mut props, key, val);
+        } else {
+            props.insert(key.to_string(), typed_prop_val(val));
+        }
+    }
+    if !classes.is_empty() {
+        props.insert("class".to_string(), PropVal::String(classes.join(" ")));
+    }
+    (!props.is_empty()).then_some((props, end + 1))
+}
+
+fn is_figure(par: &Paragraph) -> bool {
+    let mut items = par.items.iter()
+        .filter(|item| !matches!(item, ParagraphItem::Text(text) if text.trim().is_empty()));
+    matches!(items.next(), Some(ParagraphItem::Link(link)) if link.tags.contains("image"))
+        && items.next().is_none()
+}
+
+fn caption(par: &Paragraph) -> Option<String> {
+    let mut items = par.items.iter()
+        .filter(|item| !matches!(item, ParagraphItem::Text(text) if text.trim().is_empty()));
+    match (items.next(), items.next()) {
+        // only what the prop can keep: light emphasis without tags
+        (Some(ParagraphItem::Em(em)), None) if em.etype == EmType::Emphasis
+            && em.strength == EmStrength::Light && em.tags.is_empty() =>
+        {
+            Some(em.text.clone())
+        },
+        _ => None,
+    }
+}
+
+fn mark_figures_in<T>(items: &mut Vec<T>, as_par: fn(&mut T) -> Option<&mut Paragraph>) {
+    let mut i = 0;
+    while i < items.len() {
+        if as_par(&mut items[i]).is_some_and(|par| is_figure(par)) {
+            let caption = items.get_mut(i + 1).and_then(as_par).and_then(|par| caption(par));
+            if caption.is_some() {
+                items.remove(i + 1);
+            }
+            if let Some(par) = as_par(&mut items[i]) {
+                par.tags.insert("figure".to_string());
+                if let Some(caption) = caption {
+                    par.props.insert("caption".to_string(), PropVal::String(caption));
+                }
+            }
+        }
+        i += 1;
+    }
+}
+
+fn mark_section_figures(section: &mut Section) {
+    mark_figures_in(&mut section.items, |item| match item {
+        SectionItem::Paragraph(par) => Some(par),
+        SectionItem::Section(_) => None,
+    });
+    for item in &mut section.items {
+        if let SectionItem::Section(sub) = item {
+            mark_section_figures(sub);
+        }
+    }
+}
+
+/// Tag paragraphs with just an image "figure" and attach the caption paragraph after them.
+pub(crate) fn mark_figures(doc: &mut Doc) {
+    mark_figures_in(&mut doc.items, |item| match item {
+        DocItem::Paragraph(par) => Some(par),
+        DocItem::Section(_) => None,
+    });
+    for item in &mut doc.items {
+        if let DocItem::Section(section) = item {
+            mark_section_figures(section);
+        }
+    }
+}
@@ -1,20 +1,67 @@
 mod tests;
+mod prop_tests;
+mod spec_tests;
+mod options;
+mod diagnostics;
+mod positions;
+mod front_matter;
+mod prop_values;
+mod anchors;
+mod code_info;
+mod markdown_out;
+mod incodoc_out;
+mod html;
+mod footnotes;
+mod rewrite;
+mod images;
 
-use std::mem;
+pub use options::*;
+pub use diagnostics::*;
+pub use markdown_out::doc_to_md;
+pub use incodoc_out::doc_to_incodoc;
+
+use std::{ mem, ops::Range };
 
 use incodoc::*;
 use incodoc::actions::prune::PruneIncodoc;
 
+use positions::SourceMap;
+use prop_values::{ typed_prop_val, unquote };
+use anchors::Slugger;
+
 use pulldown_cmark::{
-    Parser, Options, Event, Tag, TagEnd, CodeBlockKind, LinkType, MetadataBlockKind, CowStr
+    Parser, Event, Tag, TagEnd, CodeBlockKind, LinkType, MetadataBlockKind, CowStr, Alignment
 };
 
 pub const MICRO_SECTION_HEADING_LEVEL: u8 = 100;
+const MAX_HEADING_LEVEL: u8 = 6;
 
 #[must_use]
 pub fn parse_md_to_incodoc(input: &str) -> Doc {
-    let options = Options::all();
-    let parser = Parser::new_ext(input, options);
+    parse_md_to_incodoc_with(input, &ConvertOptions::default())
+}
+
+/// Convert with options. If the conversion fails, the document is empty except for a
+/// "conversion-error" prop with the error message; use [`convert`] to get the error itself and
+/// the diagnostics.
+#[must_use]
+pub fn parse_md_to_incodoc_with(input: &str, opts: &ConvertOptions) -> Doc {
+    match convert(input, opts) {
+        Ok((doc, _)) => doc,
+        Err(err) => Doc {
+            props: Props::from([
+                ("conversion-error".to_string(), PropVal::String(err.to_string())),
+            ]),
+            ..Default::default()
+        },
+    }
+}
+
+/// Convert with options, reporting what was lost or approximated.
+pub fn convert(
+    input: &str, opts: &ConvertOptions
+) -> Result<(Doc, Vec<Diagnostic>), ConvertError> {
+    let parser = Parser::new_ext(input, opts.extensions);
 
     let mut scap = false; // string capture: if a tag started that captures a string
     let mut lcap = false; // link capture: capture em and text for links
@@ -22,13 +69,22 @@ pub fn parse_md_to_incodoc(input: &str) -> Doc {
     let mut pre_section = true;
     let mut in_list_item = false;
     let mut prev_inlined = false;
-    let mut em_lvl = 0;
-    let mut sc_lvl = 0;
-    let mut html_indent = 0;
+    let mut inline_stack = Vec::new();
+    let mut html_indent = 0usize;
+    let mut html_link = false; // link started by an inline html tag
+    let mut html_break = false; // line break tag that is not written yet
+    let mut attributes_end = 0; // end of the attributes of an image, which are not text
     let mut section_count = 0;
+    // micro sections nested deeper than this are flattened, so heading levels fit,
+    // also when collected footnotes move one level down
+    let max_section_depth = (u8::MAX - opts.micro_section_heading_level.max(MAX_HEADING_LEVEL))
+        .saturating_sub(u8::from(opts.collect_footnotes));
+    let mut flattened_sections = 0;
+    // section counts of the open details micro sections
+    let mut details_stack = Vec::new();
+    let mut meta_start = 0;
 
     let mut string = String::new();
-    let mut code_lang = String::new();
 
     let mut par_stack = Vec::new();
     let mut list_stack = Vec::new();
@@ -47,26 +103,126 @@ pub fn parse_md_to_incodoc(input: &str) -> Doc {
     let mut table_row = TableRow::default();
     let mut link = Link::default();
     let mut doc = Doc::default();
+    let mut diagnostics = Vec::new();
+    let source_map = SourceMap::new(input, opts.source_positions, opts.line_columns);
+    let link_definitions = opts.link_definitions
+        .then(|| link_definitions(&parser, &source_map))
+        .flatten();
+    let mut slugger = Slugger::default();
+    let mut anchor_links = Vec::new();
+    let mut footnote_refs = Vec::new();
+    let mut footnote_defs = Vec::new();
 
-    for event in parser {
+    for (event, range) in parser.into_offset_iter() {
         // println!("{event:?}");
+        // a line break tag at the end of a line or block adds nothing
+        if mem::take(&mut html_break)
+            && !matches!(event, Event::SoftBreak | Event::HardBreak)
+            && !is_inline_block_end(&event)
+        {
+            string.push('\n');
+            add_text_piece(
+                &inline_stack, lcap, scap, false,
+                &mut prev_inlined, &mut par, &mut link, &mut string
+            );
+        }
+        // formatted text around a link, image, code, math or footnote reference is split off from
+        // the formatted text in it
+        if !inline_stack.is_empty() && matches!(
+            event,
+            Event::Start(Tag::Link { .. } | Tag::Image { .. })
+                | Event::End(TagEnd::Link | TagEnd::Image)
+                | Event::Code(_)
+                | Event::InlineMath(_) | Event::DisplayMath(_)
+                | Event::FootnoteReference(_)
+        ) {
+            finish_text_piece(
+                &inline_stack, lcap, &opts.code_tag,
+                &mut string, &mut par.items, &mut link.items
+            );
+        }
+        // markdown formatting is balanced within a block, so what is still open at the end of
+        // the inline content was opened by an inline html tag that is never closed
+        if !inline_stack.is_empty() && (is_inline_block_end(&event) || is_block_start(&event)) {
+            finish_text_piece(
+                &inline_stack, lcap, &opts.code_tag,
+                &mut string, &mut par.items, &mut link.items
+            );
+            inline_stack.clear();
+        }
+        // an html link ends at the end of its block, or where a markdown link or image starts
+        if html_link && (
+            is_inline_block_end(&event)
+                || is_block_start(&event)
+                || matches!(event, Event::Start(Tag::Link { .. } | Tag::Image { .. }))
+        ) {
+            finish_text_piece(
+                &inline_stack, lcap, &opts.code_tag,
+                &mut string, &mut par.items, &mut link.items
+            );
+            par.items.push(ParagraphItem::Link(mem::take(&mut link)));
+            lcap = false;
+            html_link = false;
+        }
+        // a thematic break has no counterpart and only separates the text around it
+        if let Event::Rule = event {
+            diagnostics.push(Diagnostic {
+                kind: DiagnosticKind::Unsupported("thematic break".to_string()),
+                range: range.clone(),
+            });
+        }
+        // link text is text and emphasis, so other formatting in it is lost
+        if lcap && matches!(event, Event::Text(_) | Event::Code(_)) {
+            let (em, mut tags) = inline_style(&inline_stack, &opts.code_tag);
+            if let Event::Code(_) = event {
+                tags.insert(opts.code_tag.clone());
+            }
+            if em.is_none() && !tags.is_empty() {
+                let mut tags: Vec<_> = tags.into_iter().collect();
+                tags.sort();
+                diagnostics.push(Diagnostic {
+                    kind: DiagnosticKind::Unsupported(format!("{} in link text", tags.join(", "))),
+                    range: range.clone(),
+                });
+            }
+        }
         match event {
             Event::Text(text) => {
                 let inlined = matches!(&text, CowStr::Inlined(_));
-                string.push_str(&text);
-                add_text_piece(
-                    em_lvl, sc_lvl, lcap, scap, inlined,
-                    &mut prev_inlined, &mut par, &mut link, &mut string
-                );
+                let text = match text.as_ref() {
+                    _ if range.end <= attributes_end => "",
+                    // the source of the text can differ, like with smart punctuation
+                    text if range.start < attributes_end => match input.get(range.clone()) {
+                        Some(source) if source == text => &text[attributes_end - range.start..],
+                        _ => text.split_once('}').map_or(text, |(_, rest)| rest),
+                    },
+                    text => text,
+                };
+                if !text.is_empty() {
+                    string.push_str(text);
+                    add_text_piece(
+                        &inline_stack, lcap, scap, inlined,
+                        &mut prev_inlined, &mut par, &mut link, &mut string
+                    );
+                }
+            },
+            Event::SoftBreak if inline_stack.contains(&Inline::Code) => {
+                // line endings in code become spaces, like in code spans
+                string.push(' ');
             },
             Event::SoftBreak | Event::HardBreak | Event::Rule => {
                 string.push('\n');
                 add_text_piece(
-                    em_lvl, sc_lvl, lcap, scap, false,
+                    &inline_stack, lcap, scap, false,
                     &mut prev_inlined, &mut par, &mut link, &mut string
                 );
             },
             Event::Start(Tag::Paragraph) => {
+                // paragraphs merged into list items and micro sections stay apart
+                if (in_list_item || pcap) && !par.items.is_empty() {
+                    par.items.push(ParagraphItem::Text("\n\n".to_string()));
+                }
+                source_map.mark(&mut par.props, &range);
                 prev_inlined = false;
             },
             Event::End(TagEnd::Paragraph) if !in_list_item && !par.items.is_empty() && !pcap => {
@@ -77,15 +233,19 @@ pub fn parse_md_to_incodoc(input: &str) -> Doc {
                     section_items.push(SectionItem::Paragraph(par));
                 }
             },
+            Event::End(TagEnd::Paragraph) => { },
             Event::Start(Tag::Heading { level, id, classes, attrs }) => {
-                // commit current section
+                // commit current section, with what came before in the lists the heading is in
+                commit_open_lists(&mut par_stack, &mut list_stack, &mut list, &mut section_items);
                 if !par.items.is_empty() {
                     section_items.push(SectionItem::Paragraph(mem::take(&mut par)));
                 }
                 pre_sections.push((mem::take(&mut head), mem::take(&mut section_items)));
                 // set up new heading for new section
                 head.level = level as u8; // not the final head level
+                source_map.mark(&mut head.props, &range);
                 if let Some(id) = id {
+                    slugger.reserve(&id);
                     head.props.insert(
                         "id".to_string(),
                         PropVal::String(id.to_string())
@@ -101,28 +261,47 @@ pub fn parse_md_to_incodoc(input: &str) -> Doc {
                 for class in classes {
                     head.tags.insert(class.to_string());
                 }
-                scap = true;
+                // the heading content is collected in the paragraph, which is empty now
+                prev_inlined = false;
                 pre_section = false;
             },
             Event::End(TagEnd::Heading(_level)) => {
-                head.items.push(EmOrText::Text(mem::take(&mut string)));
-                scap = false;
+                let items = mem::take(&mut par.items);
+                add_heading_items(items, &mut head, &range, &mut diagnostics);
+                if opts.heading_ids && !head.props.contains_key("id") {
+                    let id = slugger.slug(&anchors::heading_text(&head));
+                    head.props.insert("id".to_string(), PropVal::String(id));
+                }
             },
             Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(language))) => {
-                if !language.is_empty(){
-                    code_lang = language.to_string();
-                }
+                source_map.mark(&mut par.props, &range);
+                source_map.mark(&mut code_block.props, &range);
+                code_info::parse_info_string(&language, &mut code_block);
+                scap = true;
+            },
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Indented)) => {
+                source_map.mark(&mut par.props, &range);
+                source_map.mark(&mut code_block.props, &range);
+                code_block.tags.insert("indented".to_string());
                 scap = true;
             },
             Event::End(TagEnd::CodeBlock) => {
-                code_block.language = mem::take(&mut code_lang);
                 code_block.code = mem::take(&mut string);
                 par.items.push(ParagraphItem::Code(Ok(mem::take(&mut code_block))));
                 scap = false;
             },
+            Event::Code(codet) if lcap => {
+                string.push_str(&codet);
+                let mut stack = inline_stack.clone();
+                stack.push(Inline::Code);
+                finish_text_piece(
+                    &stack, lcap, &opts.code_tag,
+                    &mut string, &mut par.items, &mut link.items
+                );
+            },
             Event::Code(codet) => {
                 let mut tags = Tags::default();
-                tags.insert("code".to_string());
+                tags.insert(opts.code_tag.clone());
                 par.items.push(ParagraphItem::MText(TextWithMeta {
                     text: codet.to_string(),
                     tags,
@@ -130,13 +309,16 @@ pub fn parse_md_to_incodoc(input: &str) -> Doc {
                 }));
             },
             Event::Start(Tag::List(start_nr)) => {
+                source_map.mark(&mut par.props, &range);
                 par_stack.push(mem::take(&mut par));
                 list_stack.push(mem::take(&mut list));
+                source_map.mark(&mut list.props, &range);
                 if start_nr.is_some() {
                     list.ltype = ListType::Distinct;
                 }
             },
             Event::Start(Tag::Item) => {
+                source_map.mark(&mut par.props, &range);
                 in_list_item = true;
             },
             Event::TaskListMarker(ticked) => {
@@ -150,72 +332,79 @@ pub fn parse_md_to_incodoc(input: &str) -> Doc {
                 in_list_item = false;
             },
             Event::End(TagEnd::List(_)) => {
-                par = par_stack.pop().expect("oof");
+                par = par_stack.pop()
+                    .ok_or(ConvertError::Unbalanced { construct: "list", range })?;
                 par.items.push(ParagraphItem::List(mem::take(&mut list)));
                 list = list_stack.pop().unwrap_or_default();
+                // a nested list ends inside the item of the outer list
+                in_list_item = !list_stack.is_empty();
             },
-            Event::Start(Tag::Emphasis) => {
-                finish_text_piece(
-                    em_lvl, sc_lvl, lcap, &mut string, &mut par.items, &mut link.items
-                );
-                em_lvl += 1;
-            },
-            Event::Start(Tag::Strong) => {
-                finish_text_piece(
-                    em_lvl, sc_lvl, lcap, &mut string, &mut par.items, &mut link.items
-                );
-                em_lvl += 2;
-            },
-            Event::Start(Tag::Strikethrough) => {
-                finish_text_piece(
-                    em_lvl, sc_lvl, lcap, &mut string, &mut par.items, &mut link.items
-                );
-                em_lvl = -1;
+            Event::Start(Tag::DefinitionList) => {
+                source_map.mark(&mut par.props, &range);
+                par_stack.push(mem::take(&mut par));
+                list_stack.push(mem::take(&mut list));
+                source_map.mark(&mut list.props, &range);
+                list.tags.insert("deflist".to_string());
             },
-            Event::Start(Tag::Superscript) => {
-                finish_text_piece(
-                    em_lvl, sc_lvl, lcap, &mut string, &mut par.items, &mut link.items
-                );
-                sc_lvl = 1;
+            Event::Start(Tag::DefinitionListTitle) => {
+                source_map.mark(&mut par.props, &range);
+                par.tags.insert("deflist-term".to_string());
+                prev_inlined = false;
+                in_list_item = true;
             },
-            Event::Start(Tag::Subscript) => {
-                finish_text_piece(
-                    em_lvl, sc_lvl, lcap, &mut string, &mut par.items, &mut link.items
-                );
-                sc_lvl = -1;
+            Event::Start(Tag::DefinitionListDefinition) => {
+                source_map.mark(&mut par.props, &range);
+                par.tags.insert("deflist-def".to_string());
+                in_list_item = true;
             },
-            Event::End(TagEnd::Strong) => {
-                finish_text_piece(
-                    em_lvl, sc_lvl, lcap, &mut string, &mut par.items, &mut link.items
-                );
-                em_lvl -= 2;
-            }
-            Event::End(TagEnd::Emphasis) => {
-                finish_text_piece(
-                    em_lvl, sc_lvl, lcap, &mut string, &mut par.items, &mut link.items
-                );
-                em_lvl -= 1;
+            Event::End(TagEnd::DefinitionListTitle | TagEnd::DefinitionListDefinition) => {
+                list.items.push(mem::take(&mut par));
+                in_list_item = false;
             },
-            Event::End(TagEnd::Strikethrough) => {
-                finish_text_piece(
-                    em_lvl, sc_lvl, lcap, &mut string, &mut par.items, &mut link.items
-                );
-                em_lvl += 1;
+            Event::End(TagEnd::DefinitionList) => {
+                par = par_stack.pop()
+                    .ok_or(ConvertError::Unbalanced { construct: "definition list", range })?;
+                par.items.push(ParagraphItem::List(mem::take(&mut list)));
+                list = list_stack.pop().unwrap_or_default();
+                // a nested list ends inside the item of the outer list
+                in_list_item = !list_stack.is_empty();
             },
-            Event::End(TagEnd::Superscript) => {
+            Event::Start(
+                tag @ (Tag::Emphasis | Tag::Strong | Tag::Strikethrough
+                    | Tag::Superscript | Tag::Subscript)
+            ) => {
                 finish_text_piece(
-                    em_lvl, sc_lvl, lcap, &mut string, &mut par.items, &mut link.items
+                    &inline_stack, lcap, &opts.code_tag,
+                    &mut string, &mut par.items, &mut link.items
                 );
-                sc_lvl = 0;
+                if let Some(inline) = Inline::from_tag(TagEnd::from(tag)) {
+                    inline_stack.push(inline);
+                }
             },
-            Event::End(TagEnd::Subscript) => {
+            Event::End(
+                tag @ (TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough
+                    | TagEnd::Superscript | TagEnd::Subscript)
+            ) => {
                 finish_text_piece(
-                    em_lvl, sc_lvl, lcap, &mut string, &mut par.items, &mut link.items
+                    &inline_stack, lcap, &opts.code_tag,
+                    &mut string, &mut par.items, &mut link.items
                 );
-                sc_lvl = 0;
+                // remove the innermost matching one, even if tags are not nested properly
+                if let Some(inline) = Inline::from_tag(tag)
+                    && let Some(i) = inline_stack.iter().rposition(|open| *open == inline)
+                {
+                    inline_stack.remove(i);
+                }
             },
             Event::Start(Tag::Link { link_type, dest_url, title, id }) => {
+                source_map.mark(&mut link.props, &range);
                 link.url = dest_url.to_string();
+                if let LinkType::WikiLink { .. } = link_type {
+                    resolve_wikilink(&mut link, opts, &range, &mut diagnostics);
+                }
+                if opts.heading_ids && link.url.starts_with('#') {
+                    anchor_links.push((link.url.clone(), range));
+                }
                 if !id.is_empty() {
                     link.props.insert("link-ref".to_string(), PropVal::String(id.to_string()));
                 }
@@ -225,6 +414,7 @@ pub fn parse_md_to_incodoc(input: &str) -> Doc {
                 if link_type == LinkType::Email {
                     link.tags.insert("email-address".to_string());
                 }
+                add_link_type(&mut link, link_type, opts);
                 lcap = true;
             },
             Event::End(TagEnd::Link) => {
@@ -232,7 +422,11 @@ pub fn parse_md_to_incodoc(input: &str) -> Doc {
                 lcap = false;
             },
             Event::Start(Tag::Image { link_type, dest_url, title, id }) => {
+                source_map.mark(&mut link.props, &range);
                 link.url = dest_url.to_string();
+                if let LinkType::WikiLink { .. } = link_type {
+                    resolve_wikilink(&mut link, opts, &range, &mut diagnostics);
+                }
                 if !id.is_empty() {
                     link.props.insert("link-ref".to_string(), PropVal::String(id.to_string()));
                 }
@@ -242,48 +436,210 @@ pub fn parse_md_to_incodoc(input: &str) -> Doc {
                 if link_type == LinkType::Email {
                     link.tags.insert("email-address".to_string());
                 }
+                add_link_type(&mut link, link_type, opts);
                 link.tags.insert("image".to_string());
                 lcap = true;
             },
             Event::End(TagEnd::Image) => {
+                images::add_alt(&mut link);
+                images::add_url_dimensions(&mut link);
+                if let Some((attributes, len)) = input.get(range.end..)
+                    .and_then(images::attributes)
+                {
+                    link.props.extend(attributes);
+                    attributes_end = range.end + len;
+                }
                 par.items.push(ParagraphItem::Link(mem::take(&mut link)));
                 lcap = false;
             },
             Event::Start(Tag::HtmlBlock) => {
+                if matches!(opts.html_blocks, HtmlBlockPolicy::Code | HtmlBlockPolicy::Map) {
+                    source_map.mark(&mut code_block.props, &range);
+                }
+                if opts.html_blocks != HtmlBlockPolicy::Drop {
+                    source_map.mark(&mut par.props, &range);
+                }
             },
             Event::Html(html_line) => {
                 string.push_str(&html_line);
             },
             Event::End(TagEnd::HtmlBlock) => {
-                code_block.language = "html".to_string();
-                code_block.code = mem::take(&mut string);
-                code_block.tags.insert("unconv-corp".to_string());
-                par.items.push(ParagraphItem::Code(Ok(mem::take(&mut code_block))));
+                let nestable = !in_list_item && par_stack.is_empty() && flattened_sections == 0;
+                let mapped = match opts.html_blocks {
+                    HtmlBlockPolicy::Map => html::map_block(&string).filter(|items| {
+                        details_fit(
+                            items, section_count, &details_stack, max_section_depth, nestable
+                        )
+                    }),
+                    _ => None,
+                };
+                if let Some(items) = mapped {
+                    string.clear();
+                    for item in items {
+                        match item {
+                            html::BlockItem::DetailsOpen { summary, open } => {
+                                start_microsection(
+                                    &mut section_count,
+                                    &mut pcap,
+                                    &mut section,
+                                    &mut section_stack,
+                                    &mut section_items,
+                                    &mut par,
+                                );
+                                pre_section = false;
+                                details_stack.push(section_count);
+                                section.heading = Heading {
+                                    level: opts.micro_section_heading_level
+                                        .saturating_add(section_count),
+                                    items: summary,
+                                    ..Default::default()
+                                };
+                                section.tags.insert("details".to_string());
+                                if open {
+                                    section.tags.insert("details-open".to_string());
+                                }
+                                source_map.mark(&mut section.props, &range);
+                            },
+                            html::BlockItem::DetailsClose => {
+                                details_stack.pop();
+                                end_microsection(
+                                    &mut section_count,
+                                    &mut pcap,
+                                    &mut section,
+                                    &mut section_stack,
+                                    &mut section_items,
+                                    &mut par,
+                                ).ok_or(ConvertError::Unbalanced {
+                                    construct: "details", range: range.clone()
+                                })?;
+                            },
+                            html::BlockItem::Paragraph(mut mapped) => {
+                                source_map.mark(&mut mapped.props, &range);
+                                if in_list_item || pcap || !par_stack.is_empty() {
+                                    if !par.items.is_empty() {
+                                        par.items.push(ParagraphItem::Text("\n\n".to_string()));
+                                    }
+                                    par.items.extend(mapped.items);
+                                    continue;
+                                }
+                                for par in [mem::take(&mut par), mapped] {
+                                    if par.items.is_empty() {
+                                        continue;
+                                    }
+                                    if pre_section {
+                                        doc.items.push(DocItem::Paragraph(par));
+                                    } else {
+                                        section_items.push(SectionItem::Paragraph(par));
+                                    }
+                                }
+                            },
+                        }
+                    }
+                    continue;
+                }
+                match opts.html_blocks {
+                    HtmlBlockPolicy::Code | HtmlBlockPolicy::Map => {
+                        code_block.language = "html".to_string();
+                        code_block.code = mem::take(&mut string);
+                        code_block.tags.insert(opts.unconverted_tag.clone());
+                        par.items.push(ParagraphItem::Code(Ok(mem::take(&mut code_block))));
+                    },
+                    HtmlBlockPolicy::Text => {
+                        par.items.push(ParagraphItem::Text(mem::take(&mut string)));
+                    },
+                    HtmlBlockPolicy::Drop => {
+                        string.clear();
+                    },
+                }
             },
-            Event::InlineHtml(tag) => {
-                let old = html_indent;
-                html_indent += if tag.contains("</") { -1 } else { 1 };
-                if html_indent > 0 && old == 0 {
-                    let em = Emphasis {
-                        strength: EmStrength::Light,
-                        etype: EmType::Deemphasis,
-                        text: "html(".to_string(),
-                        ..Default::default()
-                    };
-                    par.items.push(ParagraphItem::Em(em));
-                } else if html_indent == 0 && old > 0 {
-                    let em = Emphasis {
-                        strength: EmStrength::Light,
-                        etype: EmType::Deemphasis,
-                        text: ")".to_string(),
-                        ..Default::default()
-                    };
-                    par.items.push(ParagraphItem::Em(em));
+            Event::InlineHtml(_) if opts.inline_html == InlineHtmlPolicy::Strip => { },
+            Event::InlineHtml(raw) => {
+                let token = html::tokens(&raw)
+                    .filter(|tokens| tokens.len() == 1)
+                    .and_then(|mut tokens| tokens.pop());
+                match token {
+                    Some(html::Token::Open { name, attrs }) => {
+                        match (name.as_str(), Inline::from_html(&name)) {
+                            (_, Some(inline)) => {
+                                finish_text_piece(
+                                    &inline_stack, lcap, &opts.code_tag,
+                                    &mut string, &mut par.items, &mut link.items
+                                );
+                                inline_stack.push(inline);
+                            },
+                            ("br", _) => html_break = true,
+                            ("a", _) if !lcap && html::url_attr(&attrs, "href").is_some() => {
+                                finish_text_piece(
+                                    &inline_stack, lcap, &opts.code_tag,
+                                    &mut string, &mut par.items, &mut link.items
+                                );
+                                source_map.mark(&mut link.props, &range);
+                                link.url = html::url_attr(&attrs, "href").unwrap_or_default()
+                                    .to_string();
+                                if let Some(title) = html::attr(&attrs, "title") {
+                                    link.props.insert(
+                                        "title".to_string(), PropVal::String(title.to_string())
+                                    );
+                                }
+                                html_link = true;
+                                lcap = true;
+                            },
+                            ("img", _) => {
+                                finish_text_piece(
+                                    &inline_stack, lcap, &opts.code_tag,
+                                    &mut string, &mut par.items, &mut link.items
+                                );
+                                let mut image = html::image(&attrs);
+                                source_map.mark(&mut image.props, &range);
+                                if lcap {
+                                    let src = PropVal::String(image.url);
+                                    link.props.insert("image".to_string(), src);
+                                    link.items.extend(image.items);
+                                } else {
+                                    par.items.push(ParagraphItem::Link(image));
+                                }
+                            },
+                            // void elements are an empty enclosure
+                            _ if html::is_void(&name) || raw.trim_end().ends_with("/>") => {
+                                enclose_html(true, &mut html_indent, &mut par.items);
+                                enclose_html(false, &mut html_indent, &mut par.items);
+                            },
+                            _ => enclose_html(true, &mut html_indent, &mut par.items),
+                        }
+                    },
+                    Some(html::Token::Close(name)) => {
+                        match (name.as_str(), Inline::from_html(&name)) {
+                            (_, Some(inline)) => {
+                                finish_text_piece(
+                                    &inline_stack, lcap, &opts.code_tag,
+                                    &mut string, &mut par.items, &mut link.items
+                                );
+                                let open = inline_stack.iter().rposition(|open| *open == inline);
+                                if let Some(i) = open {
+                                    inline_stack.remove(i);
+                                }
+                            },
+                            ("a", _) if html_link => {
+                                finish_text_piece(
+                                    &inline_stack, lcap, &opts.code_tag,
+                                    &mut string, &mut par.items, &mut link.items
+                                );
+                                par.items.push(ParagraphItem::Link(mem::take(&mut link)));
+                                html_link = false;
+                                lcap = false;
+                            },
+                            _ if html::is_void(&name) => { },
+                            _ => enclose_html(false, &mut html_indent, &mut par.items),
+                        }
+                    },
+                    // comments, declarations and processing instructions are not content
+                    _ if raw.starts_with("<!") || raw.starts_with("<?") => { },
+                    _ => enclose_html(!raw.contains("</"), &mut html_indent, &mut par.items),
                 }
             },
             Event::InlineMath(math) => {
                 let mut tags = Tags::default();
-                tags.insert("latex-math".to_string());
+                tags.insert(opts.math_tag.clone());
                 par.items.push(ParagraphItem::MText(TextWithMeta {
                     text: math.to_string(),
                     tags,
@@ -291,39 +647,64 @@ pub fn parse_md_to_incodoc(input: &str) -> Doc {
                 }));
             },
             Event::DisplayMath(math) => {
-                code_block.language = "latex-math".to_string();
+                source_map.mark(&mut par.props, &range);
+                source_map.mark(&mut code_block.props, &range);
+                code_block.language = opts.math_tag.clone();
                 code_block.code = math.to_string();
                 code_block.mode = CodeModeHint::Replace;
                 par.items.push(ParagraphItem::Code(Ok(mem::take(&mut code_block))));
             },
             Event::FootnoteReference(reference) => {
+                footnote_refs.push((reference.to_string(), range.clone()));
+                source_map.mark(&mut link.props, &range);
                 link.url = format!("#footnote-{reference}");
                 link.tags.insert("footnote-ref".to_string());
                 link.items.push(EmOrText::Text(format!("{reference}")));
                 par.items.push(ParagraphItem::Link(mem::take(&mut link)));
             },
-            Event::Start(Tag::FootnoteDefinition(definition)) => {
-                if section_count > 0 {
-                    section.items.push(SectionItem::Paragraph(mem::take(&mut par)));
-                    section_stack.push(mem::take(&mut section));
-                } else if !par.items.is_empty() {
-                    section_items.push(SectionItem::Paragraph(mem::take(&mut par)));
+            Event::Start(tag @ (Tag::FootnoteDefinition(_) | Tag::BlockQuote(_)))
+                if flattened_sections > 0 || section_count >= max_section_depth =>
+            {
+                if let Tag::FootnoteDefinition(definition) = tag {
+                    footnote_defs.push((definition.to_string(), range.clone()));
                 }
-                section_count += 1;
-                pcap = true;
+                if flattened_sections == 0 {
+                    diagnostics.push(Diagnostic { kind: DiagnosticKind::DeepNesting, range });
+                }
+                flattened_sections += 1;
+            },
+            Event::End(TagEnd::FootnoteDefinition | TagEnd::BlockQuote(_))
+                if flattened_sections > 0 =>
+            {
+                flattened_sections -= 1;
+            },
+            Event::Start(Tag::FootnoteDefinition(definition)) => {
+                footnote_defs.push((definition.to_string(), range.clone()));
+                let items = if section_count > 0 { &mut section.items } else { &mut section_items };
+                commit_open_lists(&mut par_stack, &mut list_stack, &mut list, items);
+                start_microsection(
+                    &mut section_count,
+                    &mut pcap,
+                    &mut section,
+                    &mut section_stack,
+                    &mut section_items,
+                    &mut par,
+                );
                 pre_section = false;
                 let mut head = Heading::default();
                 head.items.push(EmOrText::Text(format!("{definition}")));
-                head.level = MICRO_SECTION_HEADING_LEVEL + section_count;
+                head.level = opts.micro_section_heading_level.saturating_add(section_count);
                 section.heading = head;
                 section.props.insert(
                     "id".to_string(),
                     PropVal::String(format!("footnote-{definition}"))
                 );
                 section.tags.insert("footnote-def".to_string());
+                source_map.mark(&mut section.props, &range);
             },
             Event::End(TagEnd::FootnoteDefinition) => {
-                end_microsection(
+                close_details(
+                    &mut details_stack,
                     &mut section_count,
                     &mut pcap,
                     &mut section,
@@ -331,29 +712,48 @@ pub fn parse_md_to_incodoc(input: &str) -> Doc {
                     &mut section_items,
                     &mut par,
                 );
+                end_microsection(
+                    &mut section_count,
+                    &mut pcap,
+                    &mut section,
+                    &mut section_stack,
+                    &mut section_items,
+                    &mut par,
+                ).ok_or(ConvertError::Unbalanced { construct: "footnote definition", range })?;
             },
-            Event::Start(Tag::MetadataBlock(MetadataBlockKind::PlusesStyle)) => {
+            Event::Start(Tag::MetadataBlock(_)) => {
+                // content starts after the opening delimiter line
+                meta_start = input[range.clone()]
+                    .find('\n')
+                    .map_or(range.end, |i| range.start + i + 1);
                 scap = true;
             },
             Event::End(TagEnd::MetadataBlock(MetadataBlockKind::PlusesStyle)) => {
-                parse_metadata_block(mem::take(&mut string), &mut doc);
+                parse_metadata_block(
+                    mem::take(&mut string), meta_start, &mut doc, &mut diagnostics
+                );
+                scap = false;
+            },
+            Event::End(TagEnd::MetadataBlock(MetadataBlockKind::YamlStyle)) => {
+                front_matter::parse_yaml_block(
+                    &mem::take(&mut string), meta_start, &mut doc, &mut diagnostics
+                );
                 scap = false;
             },
             Event::Start(Tag::BlockQuote(qtype)) => {
-                if section_count > 0 {
-                    section.items.push(SectionItem::Paragraph(mem::take(&mut par)));
-                    section_stack.push(mem::take(&mut section));
-                } else if !par.items.is_empty() {
-                    section_items.push(SectionItem::Paragraph(mem::take(&mut par)));
-                }
-                section_count += 1;
-                pcap = true;
+                let items = if section_count > 0 { &mut section.items } else { &mut section_items };
+                commit_open_lists(&mut par_stack, &mut list_stack, &mut list, items);
+                start_microsection(
+                    &mut section_count,
+                    &mut pcap,
+                    &mut section,
+                    &mut section_stack,
+                    &mut section_items,
+                    &mut par,
+                );
                 pre_section = false;
-                let mut head = Heading {
-                    level: MICRO_SECTION_HEADING_LEVEL + section_count,
-                    ..Default::default()
-                };
-                head.level = MICRO_SECTION_HEADING_LEVEL + section_count;
+                let head_level = opts.micro_section_heading_level.saturating_add(section_count);
+                let mut head = Heading { level: head_level, ..Default::default() };
                 if let Some(qtype) = qtype {
                     // set up new heading for new section
                     head.items.push(EmOrText::Text(format!("{qtype:?}")));
@@ -366,9 +766,11 @@ pub fn parse_md_to_incodoc(input: &str) -> Doc {
                     section.tags.insert("blockquote".to_string());
                 }
                 section.heading = head;
+                source_map.mark(&mut section.props, &range);
             },
             Event::End(TagEnd::BlockQuote(_)) => {
-                end_microsection(
+                close_details(
+                    &mut details_stack,
                     &mut section_count,
                     &mut pcap,
                     &mut section,
@@ -376,11 +778,25 @@ pub fn parse_md_to_incodoc(input: &str) -> Doc {
                     &mut section_items,
                     &mut par,
                 );
+                end_microsection(
+                    &mut section_count,
+                    &mut pcap,
+                    &mut section,
+                    &mut section_stack,
+                    &mut section_items,
+                    &mut par,
+                ).ok_or(ConvertError::Unbalanced { construct: "block quote", range })?;
             },
-            Event::Start(Tag::Table(_)) => {
+            Event::Start(Tag::Table(alignments)) => {
+                source_map.mark(&mut par.props, &range);
                 par_stack.push(mem::take(&mut par));
                 table_stack.push(mem::take(&mut table));
                 table_row_stack.push(mem::take(&mut table_row));
+                source_map.mark(&mut table.props, &range);
+                if alignments.iter().any(|a| *a != Alignment::None) {
+                    let aligns: Vec<_> = alignments.into_iter().map(alignment_name).collect();
+                    table.props.insert("align".to_string(), PropVal::String(aligns.join(",")));
+                }
             },
             Event::Start(Tag::TableHead) => {
                 table_row.is_header = true;
@@ -388,6 +804,7 @@ pub fn parse_md_to_incodoc(input: &str) -> Doc {
             Event::Start(Tag::TableRow) => {
             },
             Event::Start(Tag::TableCell) => {
+                source_map.mark(&mut par.props, &range);
                 pcap = true;
             },
             Event::End(TagEnd::TableCell) => {
@@ -395,20 +812,37 @@ pub fn parse_md_to_incodoc(input: &str) -> Doc {
                 table_row.items.push(mem::take(&mut par));
             },
             Event::End(TagEnd::TableHead) => {
+                if let Some(PropVal::String(aligns)) = table.props.get("align") {
+                    for (cell, align) in table_row.items.iter_mut().zip(aligns.split(',')) {
+                        if align != alignment_name(Alignment::None) {
+                            let align = PropVal::String(align.to_string());
+                            cell.props.insert("align".to_string(), align);
+                        }
+                    }
+                }
                 table.rows.push(mem::take(&mut table_row));
             },
             Event::End(TagEnd::TableRow) => {
                 table.rows.push(mem::take(&mut table_row));
             },
             Event::End(TagEnd::Table) => {
-                par = par_stack.pop().expect("oof");
+                par = par_stack.pop()
+                    .ok_or(ConvertError::Unbalanced { construct: "table", range })?;
                 par.items.push(ParagraphItem::Table(mem::take(&mut table)));
                 table = table_stack.pop().unwrap_or_default();
                 table_row = table_row_stack.pop().unwrap_or_default();
             },
-            _ => { },
         }
     }
+    close_details(
+        &mut details_stack,
+        &mut section_count,
+        &mut pcap,
+        &mut section,
+        &mut section_stack,
+        &mut section_items,
+        &mut par,
+    );
     if !par.items.is_empty() {
         section_items.push(SectionItem::Paragraph(mem::take(&mut par)));
     }
@@ -416,11 +850,46 @@ pub fn parse_md_to_incodoc(input: &str) -> Doc {
 
     let mega_section = pre_sections_to_sections(pre_sections);
     populate_doc(&mut doc, mega_section);
+    images::mark_figures(&mut doc);
+    doc.navs.extend(link_definitions);
+    footnotes::check_footnotes(&mut doc, &footnote_refs, &footnote_defs, &mut diagnostics);
+    if opts.collect_footnotes {
+        footnotes::collect_footnotes(&mut doc, opts.micro_section_heading_level);
+    }
+    source_map.mark_sections(&mut doc);
+    if opts.heading_ids {
+        anchors::resolve_anchors(&mut doc, &anchor_links, &mut diagnostics);
+    }
+    rewrite::rewrite_links(&mut doc, opts);
 
-    doc
+    Ok((doc, diagnostics))
 }
 
-fn end_microsection(
+/// Commit what came before in the lists that are still open, as a section that starts inside a
+/// list item is not part of it and comes after it.
+fn commit_open_lists(
+    par_stack: &mut [Paragraph],
+    list_stack: &mut [List],
+    list: &mut List,
+    section_items: &mut Vec<SectionItem>,
+) {
+    let depth = par_stack.len();
+    for level in 0..depth {
+        let open = if level + 1 < depth { &mut list_stack[level + 1] } else { &mut *list };
+        let outer = &mut par_stack[level];
+        let mut items = mem::take(&mut outer.items);
+        if !open.items.is_empty() {
+            let done = List { items: mem::take(&mut open.items), ..open.clone() };
+            items.push(ParagraphItem::List(done));
+        }
+        if !items.is_empty() {
+            section_items.push(SectionItem::Paragraph(Paragraph { items, ..outer.clone() }));
+        }
+    }
+}
+
+/// Open a micro section, inside the current one if there is one.
+fn start_microsection(
     section_count: &mut u8,
     pcap: &mut bool,
     section: &mut Section,
@@ -428,27 +897,97 @@ fn end_microsection(
     section_items: &mut Vec<SectionItem>,
     par: &mut Paragraph,
 ) {
-    *section_count -= 1;
-    section.items.push(SectionItem::Paragraph(mem::take(par)));
+    if *section_count > 0 {
+        if !par.items.is_empty() {
+            section.items.push(SectionItem::Paragraph(mem::take(par)));
+        }
+        section_stack.push(mem::take(section));
+    } else if !par.items.is_empty() {
+        section_items.push(SectionItem::Paragraph(mem::take(par)));
+    }
+    *section_count += 1;
+    *pcap = true;
+}
+
+/// Close the details left open inside the current micro section, which is about to end.
+fn close_details(
+    details_stack: &mut Vec<u8>,
+    section_count: &mut u8,
+    pcap: &mut bool,
+    section: &mut Section,
+    section_stack: &mut Vec<Section>,
+    section_items: &mut Vec<SectionItem>,
+    par: &mut Paragraph,
+) {
+    while details_stack.last().copied() == Some(*section_count) {
+        details_stack.pop();
+        end_microsection(section_count, pcap, section, section_stack, section_items, par);
+    }
+}
+
+/// Whether the details in a mapped html block can open and close where they are:
+/// not too deep, not in lists or tables, and not closing other micro sections.
+fn details_fit(
+    items: &[html::BlockItem],
+    mut section_count: u8,
+    details_stack: &[u8],
+    max_section_depth: u8,
+    nestable: bool,
+) -> bool {
+    let mut details_stack = details_stack.to_vec();
+    items.iter().all(|item| match item {
+        html::BlockItem::DetailsOpen { .. } if nestable && section_count < max_section_depth => {
+            section_count += 1;
+            details_stack.push(section_count);
+            true
+        },
+        html::BlockItem::DetailsClose if details_stack.last() == Some(&section_count) => {
+            details_stack.pop();
+            section_count -= 1;
+            true
+        },
+        html::BlockItem::DetailsOpen { .. } | html::BlockItem::DetailsClose => false,
+        html::BlockItem::Paragraph(_) => true,
+    })
+}
+
+/// Returns `None` if no micro section is open.
+fn end_microsection(
+    section_count: &mut u8,
+    pcap: &mut bool,
+    section: &mut Section,
+    section_stack: &mut Vec<Section>,
+    section_items: &mut Vec<SectionItem>,
+    par: &mut Paragraph,
+) -> Option<()> {
+    *section_count = section_count.checked_sub(1)?;
+    if !par.items.is_empty() {
+        section.items.push(SectionItem::Paragraph(mem::take(par)));
+    }
     if let Some(mut popped) = section_stack.pop() {
         popped.items.push(SectionItem::Section(mem::take(section)));
         *section = popped;
     }
-    if section_stack.is_empty() {
-        section_stack.clear();
+    if *section_count == 0 {
         if !section.is_contentless() {
             section_items.push(SectionItem::Section(mem::take(section)));
         }
         *pcap = false;
     }
+    Some(())
 }
 
-fn parse_metadata_block(raw: String, doc: &mut Doc) {
-    let lines = raw.lines();
+fn parse_metadata_block(
+    raw: String, offset: usize, doc: &mut Doc, diagnostics: &mut Vec<Diagnostic>
+) {
     let mut navs = Vec::new();
     let mut snav = Nav::default();
-    for line in lines {
-        let line = line.trim();
+    let mut line_start = offset;
+    for full_line in raw.split_inclusive('\n') {
+        let indent = full_line.len() - full_line.trim_start().len();
+        let range = line_start + indent..line_start + full_line.trim_end().len();
+        line_start += full_line.len();
+        let line = full_line.trim();
         let mut words = line.split(' ');
         let mut mode = ' ';
         if let Some(first_word) = words.next() {
@@ -458,7 +997,14 @@ fn parse_metadata_block(raw: String, doc: &mut Doc) {
                 "link" => mode = 'l',
                 "nav" => mode = 'n',
                 "end" => mode = 'e',
-                _ => { continue; }
+                "" => { continue; },
+                _ => {
+                    diagnostics.push(Diagnostic {
+                        kind: DiagnosticKind::MetadataLine(line.to_string()),
+                        range,
+                    });
+                    continue;
+                }
             }
         }
         match mode {
@@ -468,12 +1014,16 @@ fn parse_metadata_block(raw: String, doc: &mut Doc) {
                 }
             },
             'p' => {
-                if let Some(prop) = words.next() && let Some(val) = words.next() {
-                    doc.props.insert(
-                        prop.to_string(),
-                        PropVal::String(val.to_string())
-                    );
-                }
+                let Some(prop) = words.next() else { continue; };
+                let val = words.collect::<Vec<_>>().join(" ");
+                if val.trim().is_empty() { continue; }
+                let (text, quoted) = unquote(&val);
+                let val = if quoted || text.contains(' ') {
+                    PropVal::String(text)
+                } else {
+                    typed_prop_val(&text)
+                };
+                doc.props.insert(prop.to_string(), val);
             },
             'n' => {
                 navs.push(mem::take(&mut snav));
@@ -516,56 +1066,220 @@ fn parse_metadata_block(raw: String, doc: &mut Doc) {
     }
 }
 
-fn finish_text_piece(
-    em_lvl: i32, sc_lvl: i32, lcap: bool,
-    string: &mut String, pis: &mut Vec<ParagraphItem>, lis: &mut Vec<EmOrText>,
-) {
-    if string.is_empty() { return; }
-    let text = mem::take(string);
-    if em_lvl == 0 && sc_lvl == 0 {
-        if lcap {
-            lis.push(EmOrText::Text(text));
-        } else {
-            pis.push(ParagraphItem::Text(text));
+/// Nav tagged "link-definitions" with a link per link reference definition, in document order.
+/// The label is the text of the link.
+fn link_definitions(parser: &Parser, source_map: &SourceMap) -> Option<Nav> {
+    let mut defs: Vec<_> = parser.reference_definitions().iter().collect();
+    if defs.is_empty() {
+        return None;
+    }
+    defs.sort_by_key(|(_, def)| def.span.start);
+    let mut nav = Nav {
+        description: "link definitions".to_string(),
+        tags: Tags::from(["link-definitions".to_string()]),
+        ..Default::default()
+    };
+    for (label, def) in defs {
+        let mut link = Link {
+            url: def.dest.to_string(),
+            items: vec![EmOrText::Text(label.to_string())],
+            ..Default::default()
+        };
+        if let Some(title) = def.title.as_ref().filter(|title| !title.is_empty()) {
+            link.props.insert("title".to_string(), PropVal::String(title.to_string()));
         }
-        return;
+        source_map.mark(&mut link.props, &def.span);
+        nav.links.push(link);
     }
-    let (strength, etype) = match em_lvl {
-        2  => (EmStrength::Medium, EmType::Emphasis),
-        3  => (EmStrength::Strong, EmType::Emphasis),
-        -1 => (EmStrength::Medium, EmType::Deemphasis),
-        _  => (EmStrength::Light, EmType::Emphasis),
+    Some(nav)
+}
+
+/// Record the markdown syntax of a link or image as "link-type" prop, if enabled.
+fn add_link_type(link: &mut Link, link_type: LinkType, opts: &ConvertOptions) {
+    let name = match link_type {
+        _ if !opts.link_types => return,
+        LinkType::Inline => "inline",
+        LinkType::Reference | LinkType::ReferenceUnknown => "reference",
+        LinkType::Collapsed | LinkType::CollapsedUnknown => "collapsed",
+        LinkType::Shortcut | LinkType::ShortcutUnknown => "shortcut",
+        LinkType::Autolink | LinkType::Email => "autolink",
+        // tagged "wikilink" already
+        LinkType::WikiLink { .. } => return,
     };
-    let mut tags = Tags::default();
-    if sc_lvl == 1 {
-        tags.insert("super".to_string());
-    } else if sc_lvl == -1 {
-        tags.insert("sub".to_string());
-    }
-    if em_lvl == 0 {
-        if lcap {
-            lis.push(EmOrText::Text(text));
-        } else {
-            pis.push(ParagraphItem::MText(TextWithMeta{ text, tags, ..Default::default() }));
+    link.props.insert("link-type".to_string(), PropVal::String(name.to_string()));
+}
+
+/// Tag the link "wikilink" and let the resolver, if any, turn the page name into the url.
+/// A fragment ("Page#Heading") is kept after the resolved url; a bare fragment is not resolved.
+fn resolve_wikilink(
+    link: &mut Link, opts: &ConvertOptions, range: &Range<usize>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    link.tags.insert("wikilink".to_string());
+    let Some(resolver) = &opts.wikilink_resolver else { return; };
+    let (page, fragment) = match link.url.find('#') {
+        Some(i) => link.url.split_at(i),
+        None => (link.url.as_str(), ""),
+    };
+    if page.is_empty() { return; }
+    match resolver.resolve(page) {
+        Some(url) => link.url = format!("{url}{fragment}"),
+        None => {
+            diagnostics.push(Diagnostic {
+                kind: DiagnosticKind::UnresolvedWikilink(page.to_string()),
+                range: range.clone(),
+            });
+            link.tags.insert("unresolved-wikilink".to_string());
+        },
+    }
+}
+
+fn alignment_name(alignment: Alignment) -> &'static str {
+    match alignment {
+        Alignment::None => "none",
+        Alignment::Left => "left",
+        Alignment::Center => "center",
+        Alignment::Right => "right",
+    }
+}
+
+/// Inline formatting that is open around the text.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Inline {
+    Emphasis,
+    Strong,
+    Strikethrough,
+    Superscript,
+    Subscript,
+    Code,
+    Kbd,
+}
+
+impl Inline {
+    fn from_tag(tag: TagEnd) -> Option<Self> {
+        match tag {
+            TagEnd::Emphasis => Some(Self::Emphasis),
+            TagEnd::Strong => Some(Self::Strong),
+            TagEnd::Strikethrough => Some(Self::Strikethrough),
+            TagEnd::Superscript => Some(Self::Superscript),
+            TagEnd::Subscript => Some(Self::Subscript),
+            _ => None,
+        }
+    }
+
+    /// Formatting of an inline html tag, by lowercase name.
+    fn from_html(name: &str) -> Option<Self> {
+        match name {
+            "em" | "i" => Some(Self::Emphasis),
+            "strong" | "b" => Some(Self::Strong),
+            "del" | "s" => Some(Self::Strikethrough),
+            "sup" => Some(Self::Superscript),
+            "sub" => Some(Self::Subscript),
+            "code" => Some(Self::Code),
+            "kbd" => Some(Self::Kbd),
+            _ => None,
         }
+    }
+}
+
+/// Whether the event ends a block with inline content.
+fn is_inline_block_end(event: &Event) -> bool {
+    matches!(
+        event,
+        Event::End(TagEnd::Paragraph | TagEnd::Heading(_) | TagEnd::TableCell | TagEnd::Item
+            | TagEnd::DefinitionListTitle | TagEnd::DefinitionListDefinition)
+    )
+}
+
+/// Whether the event starts a block, which ends the inline content before it in a tight list.
+fn is_block_start(event: &Event) -> bool {
+    matches!(event, Event::Rule | Event::Start(
+        Tag::Paragraph | Tag::Heading { .. } | Tag::BlockQuote(_) | Tag::CodeBlock(_)
+            | Tag::HtmlBlock | Tag::List(_) | Tag::Item | Tag::FootnoteDefinition(_)
+            | Tag::Table(_) | Tag::DefinitionList | Tag::DefinitionListTitle
+            | Tag::DefinitionListDefinition | Tag::MetadataBlock(_)
+    ))
+}
+
+/// Open or close the deemphasised "html(" ... ")" enclosure around text inside unmapped tags.
+fn enclose_html(opening: bool, html_indent: &mut usize, items: &mut Vec<ParagraphItem>) {
+    let text = if opening {
+        *html_indent += 1;
+        "html("
+    } else if *html_indent > 0 {
+        *html_indent -= 1;
+        ")"
     } else {
-        let em = Emphasis { strength, etype, text, tags, ..Default::default() };
-        if lcap {
-            lis.push(EmOrText::Em(em));
-        } else {
-            pis.push(ParagraphItem::Em(em));
+        return;
+    };
+    if *html_indent == usize::from(opening) {
+        items.push(ParagraphItem::Em(Emphasis {
+            strength: EmStrength::Light,
+            etype: EmType::Deemphasis,
+            text: text.to_string(),
+            ..Default::default()
+        }));
+    }
+}
+
+/// Emphasis and tags of text inside the open inline formatting.
+/// Emphasis counts as one and strong as two: one is light, two medium and more is strong.
+/// Strikethrough alone is medium deemphasis; combined with emphasis it is a tag.
+/// Returns `None` for emphasis if the text is not (de-)emphasised.
+fn inline_style(stack: &[Inline], code_tag: &str) -> (Option<(EmStrength, EmType)>, Tags) {
+    let mut weight = 0;
+    let mut tags = Tags::default();
+    for inline in stack {
+        match inline {
+            Inline::Emphasis => weight += 1,
+            Inline::Strong => weight += 2,
+            Inline::Strikethrough => { tags.insert("strikethrough".to_string()); },
+            Inline::Superscript => { tags.insert("super".to_string()); },
+            Inline::Subscript => { tags.insert("sub".to_string()); },
+            Inline::Code => { tags.insert(code_tag.to_string()); },
+            Inline::Kbd => { tags.insert("kbd".to_string()); },
         }
     }
+    let em = match weight {
+        0 if tags.remove("strikethrough") => Some((EmStrength::Medium, EmType::Deemphasis)),
+        0 => None,
+        1 => Some((EmStrength::Light, EmType::Emphasis)),
+        2 => Some((EmStrength::Medium, EmType::Emphasis)),
+        _ => Some((EmStrength::Strong, EmType::Emphasis)),
+    };
+    (em, tags)
+}
+
+fn finish_text_piece(
+    inline_stack: &[Inline], lcap: bool, code_tag: &str,
+    string: &mut String, pis: &mut Vec<ParagraphItem>, lis: &mut Vec<EmOrText>,
+) {
+    if string.is_empty() { return; }
+    let text = mem::take(string);
+    let (em, tags) = inline_style(inline_stack, code_tag);
+    match em {
+        None if lcap => lis.push(EmOrText::Text(text)),
+        None if tags.is_empty() => pis.push(ParagraphItem::Text(text)),
+        None => pis.push(ParagraphItem::MText(TextWithMeta{ text, tags, ..Default::default() })),
+        Some((strength, etype)) => {
+            let em = Emphasis { strength, etype, text, tags, ..Default::default() };
+            if lcap {
+                lis.push(EmOrText::Em(em));
+            } else {
+                pis.push(ParagraphItem::Em(em));
+            }
+        },
+    }
 }
 
 #[allow(clippy::too_many_arguments)]
 fn add_text_piece(
-    em_lvl: i32, sc_lvl: i32, lcap: bool, scap: bool, inlined: bool,
+    inline_stack: &[Inline], lcap: bool, scap: bool, inlined: bool,
     prev_inlined: &mut bool, par: &mut Paragraph, link: &mut Link, string: &mut String,
 ) {
-    if lcap && em_lvl == 0 && sc_lvl == 0 {
+    if lcap && inline_stack.is_empty() {
         link.items.push(EmOrText::Text(mem::take(string)));
-    } else if !scap && em_lvl == 0 && sc_lvl == 0 {
+    } else if !scap && inline_stack.is_empty() {
         if let (false, false) = (inlined, &prev_inlined) {
             par.items.push(ParagraphItem::Text(mem::take(string)));
         } else {
@@ -582,6 +1296,78 @@ fn add_text_piece(
     }
 }
 
+/// Heading items can only be text and emphasis.
+/// Tagged text becomes text with a prop named after the tag and the item index.
+/// Links become their text with props named after the index of the first item: "link" for the
+/// url, "link-end" for the index after the last item, and "link-tags" and "link-title" if any.
+/// Blocks and image dimensions have no place in a heading and are dropped with a diagnostic.
+fn add_heading_items(
+    items: Vec<ParagraphItem>, head: &mut Heading,
+    range: &Range<usize>, diagnostics: &mut Vec<Diagnostic>,
+) {
+    let mut dropped = |name: &str| diagnostics.push(Diagnostic {
+        kind: DiagnosticKind::Unsupported(format!("{name} in heading")),
+        range: range.clone(),
+    });
+    // whether text can be appended to the last text item
+    let mut mergeable = false;
+    for item in items {
+        match item {
+            ParagraphItem::Text(text) => {
+                if mergeable && let Some(EmOrText::Text(last)) = head.items.last_mut() {
+                    last.push_str(&text);
+                } else {
+                    head.items.push(EmOrText::Text(text));
+                }
+                mergeable = true;
+            },
+            ParagraphItem::Em(em) => {
+                head.items.push(EmOrText::Em(em));
+                mergeable = false;
+            },
+            ParagraphItem::MText(mtext) => {
+                let index = head.items.len();
+                for tag in mtext.tags {
+                    let val = PropVal::String(mtext.text.clone());
+                    head.props.insert(format!("{tag}-{index}"), val);
+                }
+                head.items.push(EmOrText::Text(mtext.text));
+                mergeable = false;
+            },
+            ParagraphItem::Link(link) => {
+                let index = head.items.len();
+                head.items.extend(link.items);
+                let mut prop = |name: &str, val| head.props.insert(format!("{name}-{index}"), val);
+                prop("link", PropVal::String(link.url));
+                prop("link-end", PropVal::Int(head.items.len() as i64));
+                if !link.tags.is_empty() {
+                    let mut tags: Vec<_> = link.tags.into_iter().collect();
+                    tags.sort();
+                    prop("link-tags", PropVal::String(tags.join(" ")));
+                }
+                if let Some(title) = link.props.get("title") {
+                    prop("link-title", title.clone());
+                }
+                if link.props.contains_key("width") || link.props.contains_key("height") {
+                    dropped("image size");
+                }
+                mergeable = false;
+            },
+            // display math, which is kept as inline math
+            ParagraphItem::Code(Ok(code)) if code.mode == CodeModeHint::Replace => {
+                let index = head.items.len();
+                let val = PropVal::String(code.code.clone());
+                head.props.insert(format!("{}-{index}", code.language), val);
+                head.items.push(EmOrText::Text(code.code));
+                mergeable = false;
+            },
+            ParagraphItem::Code(_) => dropped("code block"),
+            ParagraphItem::List(_) => dropped("list"),
+            ParagraphItem::Table(_) => dropped("table"),
+        }
+    }
+}
+
 fn pre_sections_to_sections(mut pres: Vec<(Heading, Vec<SectionItem>)>) -> Section {
     if pres.is_empty() {
         return Section::default();
@@ -625,7 +1411,7 @@ fn populate_doc(doc: &mut Doc, mega: Section) {
     for item in mega.items {
         match item {
             SectionItem::Section(section) => {
-                doc.items.push(DocItem::Section(downgraded_section(section)));
+                doc.items.push(DocItem::Section(downgraded_section(section, 0)));
             }
             SectionItem::Paragraph(par) => {
                 doc.items.push(DocItem::Paragraph(par));
@@ -634,11 +1420,14 @@ fn populate_doc(doc: &mut Doc, mega: Section) {
     }
 }
 
-fn downgraded_section(mut section: Section) -> Section {
-    section.heading.level -= section.heading.level.min(1);
+/// Lower the heading level by one, but keep it above the level of the parent section,
+/// which micro sections with a low heading level would not be otherwise.
+fn downgraded_section(mut section: Section, min_level: u8) -> Section {
+    let level = section.heading.level.saturating_sub(1).max(min_level);
+    section.heading.level = level;
     for item in &mut section.items {
         if let SectionItem::Section(sub_section) = item {
-            *sub_section = downgraded_section(mem::take(sub_section));
+            *sub_section = downgraded_section(mem::take(sub_section), level.saturating_add(1));
         }
     }
     section
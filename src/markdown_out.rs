@@ -0,0 +1,1068 @@
+//! Incodoc to markdown.
+//!
+//! Understands the tags and props that the conversion with default options produces and writes
+//! markdown that converts back into an equivalent document: document tags, props and navs become
+//! YAML front matter, sections become headings, micro sections become block quotes and footnote
+//! definitions, and tagged text, emphasis, links, code, lists and tables get their markdown syntax
+//! back. Links keep their reference style if they have a "link-type" prop, with the link
+//! reference definitions at the end. Other tags and props, such as source positions, are dropped.
+
+use crate::prop_values::typed_prop_val;
+
+use incodoc::*;
+
+use std::{ collections::HashMap, fmt::Write, mem };
+
+const CODE_TAG: &str = "code";
+const MATH_TAG: &str = "latex-math";
+const UNCONVERTED_TAG: &str = "unconv-corp";
+/// Placed next to a run of markers whose neighbour there may not be alphanumeric, which then is
+/// written as a character reference by [`resolve_flanks`].
+const FLANK: char = '\u{FDD0}';
+/// Tags of text and parts of links that become a prop named after them and the item index in
+/// headings.
+const INDEXED_HEADING_PROPS: [&str; 8] = [
+    "link", "link-end", "link-tags", "link-title", CODE_TAG, MATH_TAG, "super", "sub",
+];
+
+/// Convert a document to markdown.
+#[must_use]
+pub fn doc_to_md(doc: &Doc) -> String {
+    let mut doc = doc.clone();
+    let definitions = link_definitions(&mut doc);
+    let mut output = String::new();
+    front_matter_out(&doc, &mut output);
+    let mut blocks = Blocks::default();
+    for item in &doc.items {
+        match item {
+            DocItem::Paragraph(par) => paragraph_out(par, &mut blocks),
+            DocItem::Section(section) => section_out(section, &mut blocks),
+        }
+    }
+    blocks.push(link_definitions_out(&definitions));
+    output.push_str(&blocks.join("\n\n"));
+    if !output.ends_with('\n') {
+        output.push('\n');
+    }
+    resolve_flanks(&output)
+}
+
+/// Remove the [`FLANK`] marks, writing alphanumeric characters next to them as character
+/// references, which are punctuation to the runs of markers.
+fn resolve_flanks(md: &str) -> String {
+    let chars: Vec<char> = md.chars().collect();
+    let mut res = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c == FLANK {
+            continue;
+        }
+        let flanked = (i > 0 && chars[i - 1] == FLANK) || chars.get(i + 1) == Some(&FLANK);
+        if flanked && !is_punctuation(c) && !c.is_whitespace() {
+            let _ = write!(res, "&#{};", u32::from(c));
+        } else {
+            res.push(c);
+        }
+    }
+    res
+}
+
+/// Markdown blocks of one container.
+#[derive(Default)]
+struct Blocks {
+    blocks: Vec<String>,
+    /// Whether the last block is a list, if it is ordered and if it used the alternative markers.
+    /// Lists of the same kind directly after each other alternate markers so they do not merge.
+    last_list: Option<(bool, bool)>,
+    /// Indices of the blocks that directly follow a list.
+    after_list: Vec<usize>,
+}
+
+impl Blocks {
+    fn push(&mut self, block: String) {
+        if !block.trim().is_empty() {
+            if self.last_list.is_some() {
+                self.after_list.push(self.blocks.len());
+            }
+            self.blocks.push(block);
+            self.last_list = None;
+        }
+    }
+
+    fn push_list(&mut self, list: &List) {
+        let ordered = list.ltype == ListType::Distinct;
+        let alt = self.last_list == Some((ordered, false));
+        let block = list_out(list, alt);
+        if !block.is_empty() {
+            self.blocks.push(block);
+            self.last_list = Some((ordered, alt));
+        }
+    }
+
+    fn join(&self, sep: &str) -> String {
+        self.blocks.join(sep)
+    }
+
+    /// Join the blocks on separate lines, as in a tight list item. Blocks after a list get a
+    /// blank line before them so they do not continue its last item.
+    fn join_tight(&self) -> String {
+        let mut res = String::new();
+        for (i, block) in self.blocks.iter().enumerate() {
+            if i > 0 {
+                res.push_str(if self.after_list.contains(&i) { "\n\n" } else { "\n" });
+            }
+            res.push_str(block);
+        }
+        res
+    }
+}
+
+/// Prefix the first line with `first` and the other lines with `rest`.
+/// Empty lines get `rest` without trailing whitespace.
+fn prefixed(text: &str, first: &str, rest: &str) -> String {
+    let mut res = String::new();
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            res.push('\n');
+        }
+        if i == 0 {
+            res.push_str(first);
+        } else if line.is_empty() {
+            res.push_str(rest.trim_end());
+        } else {
+            res.push_str(rest);
+        }
+        res.push_str(line);
+    }
+    res
+}
+
+/// Whether only whitespace is before the output position on its line,
+/// which is dropped once lines are trimmed.
+fn is_line_start(output: &str) -> bool {
+    let output = output.trim_end_matches([' ', '\t']);
+    output.is_empty() || output.ends_with('\n')
+}
+
+/// Write text, escaping everything that markdown would otherwise interpret.
+fn text_out(text: &str, output: &mut String) {
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let line_start = is_line_start(output);
+        match c {
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '{' | '$' | '~' | '^' | '|' => {
+                output.push('\\');
+                output.push(c);
+            },
+            '#' | '>' | '-' | '+' | '=' | ':' if line_start => {
+                output.push('\\');
+                output.push(c);
+            },
+            '0'..='9' if line_start => {
+                output.push(c);
+                while let Some((_, d)) = chars.next_if(|(_, d)| d.is_ascii_digit()) {
+                    output.push(d);
+                }
+                if let Some((_, d)) = chars.next_if(|(_, d)| matches!(d, '.' | ')')) {
+                    output.push('\\');
+                    output.push(d);
+                }
+            },
+            // smart punctuation would make quotes curly and dashes and ellipses out of runs
+            '\'' | '"' => {
+                output.push('\\');
+                output.push(c);
+            },
+            '-' if output.ends_with('-') => output.push_str("\\-"),
+            '.' if output.ends_with("..") => output.push_str("\\."),
+            '&' if is_entity(&text[i + 1..]) => output.push_str("\\&"),
+            FLANK => output.push_str("&#xFDD0;"),
+            // would make a shortcut reference link before it an inline link
+            '(' if output.ends_with(']') => output.push_str("\\("),
+            _ => output.push(c),
+        }
+    }
+}
+
+/// Whether the text after an '&' would make it an entity or character reference.
+fn is_entity(text: &str) -> bool {
+    let name = text.strip_prefix('#').unwrap_or(text);
+    let len = name.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(name.len());
+    len > 0 && name[len..].starts_with(';')
+}
+
+fn longest_run(text: &str, c: char) -> usize {
+    text.split(|d| d != c).map(str::len).max().unwrap_or(0)
+}
+
+fn code_span_out(code: &str, output: &mut String) {
+    // line endings would become spaces anyway
+    let code = &code.replace('\n', " ");
+    // a fence right after another one would run into it
+    if output.ends_with('`') {
+        output.push_str("<code>");
+        // escaped, punctuation is not made smart either
+        for c in code.chars() {
+            if c.is_ascii_punctuation() { output.push('\\'); }
+            output.push(c);
+        }
+        output.push_str("</code>");
+        return;
+    }
+    let fence = "`".repeat(longest_run(code, '`') + 1);
+    let pad = code.starts_with('`') || code.ends_with('`')
+        || (code.starts_with(' ') && code.ends_with(' ') && !code.trim().is_empty());
+    output.push_str(&fence);
+    if pad { output.push(' '); }
+    output.push_str(code);
+    if pad { output.push(' '); }
+    output.push_str(&fence);
+}
+
+/// Markers around text for super/subscript and strikethrough tags, inside strikethrough if
+/// `struck`.
+fn tag_markers(tags: &Tags, struck: bool) -> String {
+    let mut markers = String::new();
+    if tags.contains("strikethrough") { markers.push_str("~~"); }
+    let (sup, sub) = (tags.contains("super"), tags.contains("sub"));
+    // pulldown-cmark loses a `~` or `^` run around another one that could also close,
+    // so super and subscript in strikethrough or in each other are html
+    let html = usize::from(struck || tags.contains("strikethrough")) + usize::from(sup)
+        + usize::from(sub) > 1;
+    if sup { markers.push_str(if html { "<sup>" } else { "^" }); }
+    if sub { markers.push_str(if html { "<sub>" } else { "~" }); }
+    markers
+}
+
+/// The markers that close the opening markers.
+fn closing_markers(open: &str) -> String {
+    let mut close = String::new();
+    let mut rest = open;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' && let Some(end) = rest.find('>') {
+            close.insert_str(0, &format!("</{}>", &rest[1..end]));
+            rest = &rest[end + 1..];
+        } else {
+            close.insert(0, c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    close
+}
+
+/// Text before and after the first blank line.
+fn split_at_blank_line(text: &str) -> Option<(&str, &str)> {
+    let mut ends = text.match_indices('\n').map(|(i, _)| i);
+    let mut prev = ends.next()?;
+    for end in ends {
+        if text[prev + 1..end].trim_matches([' ', '\t']).is_empty() {
+            return Some((&text[..prev], &text[end + 1..]));
+        }
+        prev = end;
+    }
+    None
+}
+
+/// Whether markdown counts the character as punctuation for delimiter runs; all non-ASCII
+/// characters that are not alphanumeric or whitespace are taken to be.
+fn is_punctuation(c: char) -> bool {
+    c.is_ascii_punctuation() || !(c.is_ascii() || c.is_alphanumeric() || c.is_whitespace())
+}
+
+/// Write text between markers, keeping surrounding whitespace outside of them.
+///
+/// A run of markers with punctuation on its inner side only opens or closes next to whitespace
+/// or punctuation, and a `_` run never between alphanumerics, so those runs get a [`FLANK`] on
+/// the outer side. A `*` run right after another one would merge with it, so it becomes `_`,
+/// and `~` and `^` runs become html tags.
+fn marked_out(text: &str, open: &str, output: &mut String) {
+    let trimmed = text.trim();
+    if trimmed.is_empty() || open.is_empty() {
+        text_out(text, output);
+        return;
+    }
+    // markers do not reach over a paragraph break
+    if let Some((first, rest)) = split_at_blank_line(text) {
+        marked_out(first.trim_end(), open, output);
+        output.push_str("\n\n");
+        marked_out(rest.trim_start(), open, output);
+        return;
+    }
+    let start = text.len() - text.trim_start().len();
+    output.push_str(&text[..start]);
+    // a run right after one of the same character would merge with it
+    let open = match open.chars().next() {
+        Some(c @ ('*' | '~' | '^')) if output.trim_end_matches(FLANK).ends_with(c) => {
+            let run = open.len() - open.trim_start_matches(c).len();
+            let outer = match (c, run) {
+                ('*', _) => "_".repeat(run),
+                ('~', 2) => "<del>".to_string(),
+                ('~', _) => "<sub>".to_string(),
+                _ => "<sup>".to_string(),
+            };
+            format!("{outer}{}", &open[run..])
+        },
+        _ => open.to_string(),
+    };
+    let open_at = output.len();
+    output.push_str(&open);
+    let text_at = output.len();
+    text_out(trimmed, output);
+    let delimiter = open.chars().next().unwrap_or_default();
+    let underscore = delimiter == '_';
+    let flanked = matches!(delimiter, '*' | '_' | '~' | '^');
+    // the marker inside the outer run, if any, is its neighbour on the inner side
+    let marker = open.trim_start_matches(delimiter).chars().next();
+    let after_open = marker.or_else(|| output[text_at..].chars().next());
+    let before_close = marker.or_else(|| output[text_at..].chars().next_back());
+    output.push_str(&closing_markers(&open));
+    if flanked && (underscore || before_close.is_some_and(is_punctuation)) {
+        output.push(FLANK);
+    }
+    if flanked && (underscore || after_open.is_some_and(is_punctuation)) {
+        output.insert(open_at, FLANK);
+    }
+    output.push_str(&text[start + trimmed.len()..]);
+}
+
+fn em_out(em: &Emphasis, output: &mut String) {
+    let mut open = match (em.etype, em.strength) {
+        (EmType::Deemphasis, _) => "~~",
+        (EmType::Emphasis, EmStrength::Light) => "*",
+        (EmType::Emphasis, EmStrength::Medium) => "**",
+        (EmType::Emphasis, EmStrength::Strong) => "***",
+    }.to_string();
+    open.push_str(&tag_markers(&em.tags, em.etype == EmType::Deemphasis));
+    marked_out(&em.text, &open, output);
+}
+
+fn kbd_out(text: &str, output: &mut String) {
+    // html tags do not reach over a paragraph break either
+    if let Some((first, rest)) = split_at_blank_line(text) {
+        kbd_out(first.trim_end(), output);
+        output.push_str("\n\n");
+        kbd_out(rest.trim_start(), output);
+        return;
+    }
+    if text.is_empty() {
+        return;
+    }
+    output.push_str("<kbd>");
+    text_out(text, output);
+    output.push_str("</kbd>");
+}
+
+fn mtext_out(mtext: &TextWithMeta, output: &mut String) {
+    if mtext.tags.contains(CODE_TAG) {
+        code_span_out(&mtext.text, output);
+    } else if mtext.tags.contains("kbd") {
+        kbd_out(&mtext.text, output);
+    } else if mtext.tags.contains(MATH_TAG) {
+        math_out(&mtext.text, output);
+    } else {
+        marked_out(&mtext.text, &tag_markers(&mtext.tags, false), output);
+    }
+}
+
+fn math_out(math: &str, output: &mut String) {
+    // empty inline math is written as display math, "$$" would be plain text
+    let delimiter = if math.is_empty() { "$$" } else { "$" };
+    let _ = write!(output, "{delimiter}{math}{delimiter}");
+}
+
+/// Write text and emphasis; adjacent text is escaped as a whole.
+fn em_or_text_items_out(items: &[EmOrText], output: &mut String) {
+    let mut text = String::new();
+    for item in items {
+        match item {
+            EmOrText::Text(t) => text.push_str(t),
+            EmOrText::Em(em) => {
+                text_out(&mem::take(&mut text), output);
+                em_out(em, output);
+            },
+        }
+    }
+    text_out(&text, output);
+}
+
+fn plain_text(items: &[EmOrText]) -> String {
+    items.iter().map(|item| match item {
+        EmOrText::Text(text) => text.as_str(),
+        EmOrText::Em(em) => em.text.as_str(),
+    }).collect()
+}
+
+fn link_destination_out(url: &str, output: &mut String) {
+    if url.is_empty() || url.contains([' ', '(', ')', '<', '>']) {
+        output.push('<');
+        for c in url.chars() {
+            if matches!(c, '<' | '>' | '\\') { output.push('\\'); }
+            output.push(c);
+        }
+        output.push('>');
+    } else {
+        output.push_str(url);
+    }
+}
+
+fn prop_str<'a>(props: &'a Props, key: &str) -> Option<&'a str> {
+    match props.get(key) {
+        Some(PropVal::String(val)) => Some(val),
+        _ => None,
+    }
+}
+
+/// Link labels match case-insensitively and with whitespace collapsed.
+fn label_key(label: &str) -> String {
+    label.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// Link reference definitions to write at the end: those of the "link-definitions" nav and
+/// those that links in a reference style need. Links that disagree with the definition of their
+/// label, or whose text does not match it where it has to, fall back to the inline style.
+fn link_definitions(doc: &mut Doc) -> Vec<Link> {
+    let mut definitions: Vec<Link> = doc.navs.iter()
+        .filter(|nav| nav.tags.contains("link-definitions"))
+        .flat_map(|nav| nav.links.iter().cloned())
+        .collect();
+    let mut index = HashMap::new();
+    for (i, def) in definitions.iter().enumerate() {
+        index.entry(label_key(&plain_text(&def.items))).or_insert(i);
+    }
+    for link in doc.links_mut(false) {
+        let style = prop_str(&link.props, "link-type").unwrap_or_default();
+        if !matches!(style, "reference" | "collapsed" | "shortcut") {
+            continue;
+        }
+        let mut text = String::new();
+        em_or_text_items_out(&link.items, &mut text);
+        let label = prop_str(&link.props, "link-ref").map(label_key);
+        let fits = match label {
+            Some(label) if style == "reference" || label == label_key(&text) => {
+                let title = link.props.get("title");
+                let i = *index.entry(label).or_insert_with(|| {
+                    definitions.push(Link {
+                        url: link.url.clone(),
+                        items: vec![EmOrText::Text(
+                            prop_str(&link.props, "link-ref").unwrap_or_default().to_string()
+                        )],
+                        props: title.map(|title| ("title".to_string(), title.clone()))
+                            .into_iter()
+                            .collect(),
+                        ..Default::default()
+                    });
+                    definitions.len() - 1
+                });
+                definitions[i].url == link.url && definitions[i].props.get("title") == title
+            },
+            _ => false,
+        };
+        if !fits {
+            link.props.remove("link-type");
+        }
+    }
+    definitions
+}
+
+fn link_definitions_out(definitions: &[Link]) -> String {
+    let mut output = String::new();
+    for def in definitions {
+        let _ = write!(output, "[{}]: ", plain_text(&def.items));
+        link_destination_out(&def.url, &mut output);
+        if let Some(title) = prop_str(&def.props, "title") {
+            let title = title.replace('\\', "\\\\").replace('"', "\\\"");
+            let _ = write!(output, " \"{title}\"");
+        }
+        output.push('\n');
+    }
+    output.trim_end().to_string()
+}
+
+fn link_out(link: &Link, output: &mut String) {
+    if link.tags.contains("footnote-ref") {
+        let label = link.url.strip_prefix("#footnote-").unwrap_or(&link.url);
+        let _ = write!(output, "[^{label}]");
+        return;
+    }
+    if link.tags.contains("footnote-backref") {
+        return;
+    }
+    let image = link.tags.contains("image");
+    let plain = plain_text(&link.items);
+    let mut text = String::new();
+    em_or_text_items_out(&link.items, &mut text);
+    let style = prop_str(&link.props, "link-type").unwrap_or_default();
+    let label = prop_str(&link.props, "link-ref");
+    if image { output.push('!'); }
+    if link.tags.contains("wikilink") {
+        if plain == link.url {
+            let _ = write!(output, "[[{}]]", link.url);
+        } else {
+            let _ = write!(output, "[[{}|{text}]]", link.url);
+        }
+    } else if let Some(label) = label && style == "reference" {
+        let _ = write!(output, "[{text}][{label}]");
+    } else if label.is_some() && style == "collapsed" {
+        let _ = write!(output, "[{text}][]");
+    } else if label.is_some() && style == "shortcut" {
+        let _ = write!(output, "[{text}]");
+    } else if style != "inline" && !image && plain == link.url
+        && (link.tags.contains("email-address") || link.url.contains("://"))
+        && !link.url.contains([' ', '<', '>'])
+    {
+        let _ = write!(output, "<{}>", link.url);
+    } else {
+        let _ = write!(output, "[{text}](");
+        link_destination_out(&link.url, output);
+        if let Some(PropVal::String(title)) = link.props.get("title") {
+            let title = title.replace('\\', "\\\\").replace('"', "\\\"");
+            let _ = write!(output, " \"{title}\"");
+        }
+        output.push(')');
+    }
+    if image {
+        attributes_out(&link.props, output);
+    }
+}
+
+/// Props of links that are written as part of the link, or not at all.
+const LINK_PROPS: [&str; 8] = [
+    "alt", "title", "link-type", "link-ref", "src-start", "src-end", "src-line", "src-column",
+];
+
+/// Pandoc style attributes of the other props of an image: id, classes, dimensions and the rest.
+fn attributes_out(props: &Props, output: &mut String) {
+    let mut attrs = Vec::new();
+    if let Some(id) = prop_str(props, "id") {
+        attrs.push(format!("#{id}"));
+    }
+    if let Some(classes) = prop_str(props, "class") {
+        attrs.extend(classes.split_whitespace().map(|class| format!(".{class}")));
+    }
+    let rest = sorted_props(props).into_iter()
+        .filter(|(key, _)| !["id", "class", "width", "height"].contains(&key.as_str()))
+        .filter(|(key, _)| !LINK_PROPS.contains(&key.as_str()));
+    let dimensions = ["width", "height"].into_iter()
+        .filter_map(|key| props.get(key).map(|val| (key, val)));
+    for (key, val) in dimensions.chain(rest.map(|(key, val)| (key.as_str(), val))) {
+        let Some(val) = prop_val_text(val) else { continue; };
+        let val = val.replace('"', "");
+        if val.contains(|c: char| c.is_whitespace() || c == '}') {
+            attrs.push(format!("{key}=\"{val}\""));
+        } else {
+            attrs.push(format!("{key}={val}"));
+        }
+    }
+    if !attrs.is_empty() {
+        let _ = write!(output, "{{{}}}", attrs.join(" "));
+    }
+}
+
+/// Write inline items; returns `false` for block items, which are not written.
+fn inline_out(item: &ParagraphItem, output: &mut String) -> bool {
+    match item {
+        ParagraphItem::Text(text) => text_out(text, output),
+        ParagraphItem::MText(mtext) => mtext_out(mtext, output),
+        ParagraphItem::Em(em) => em_out(em, output),
+        ParagraphItem::Link(link) => link_out(link, output),
+        ParagraphItem::Code(_) | ParagraphItem::List(_) | ParagraphItem::Table(_) => return false,
+    }
+    true
+}
+
+/// Write the inline items; adjacent text is escaped as a whole.
+/// Block items are passed to `block`, after the inline items before them are written.
+fn inline_items_out(
+    items: &[ParagraphItem], output: &mut String, mut block: impl FnMut(&ParagraphItem, &mut String)
+) {
+    let mut text = String::new();
+    for item in items {
+        if let ParagraphItem::Text(t) = item {
+            text.push_str(t);
+            continue;
+        }
+        text_out(&mem::take(&mut text), output);
+        if !inline_out(item, output) {
+            block(item, output);
+        }
+    }
+    text_out(&text, output);
+}
+
+fn paragraph_out(par: &Paragraph, blocks: &mut Blocks) {
+    let mut inline = String::new();
+    inline_items_out(&par.items, &mut inline, |item, inline| {
+        blocks.push(mem_trimmed(inline));
+        match item {
+            // indented code right after a list would continue its last item
+            ParagraphItem::Code(Ok(code))
+                if blocks.last_list.is_some() && code.tags.contains("indented") =>
+            {
+                let mut code = code.clone();
+                code.tags.remove("indented");
+                blocks.push(code_block_out(&code));
+            },
+            ParagraphItem::Code(Ok(code)) => blocks.push(code_block_out(code)),
+            ParagraphItem::List(list) => blocks.push_list(list),
+            ParagraphItem::Table(table) => blocks.push(table_out(table)),
+            _ => { },
+        }
+    });
+    blocks.push(mem_trimmed(&mut inline));
+    if par.tags.contains("figure")
+        && let Some(PropVal::String(caption)) = par.props.get("caption")
+    {
+        let mut output = String::new();
+        em_out(&Emphasis { text: caption.clone(), ..Default::default() }, &mut output);
+        blocks.push(output);
+    }
+}
+
+/// Take the inline markdown, without whitespace around lines and runs of blank lines,
+/// which would be dropped when parsing it again.
+fn mem_trimmed(string: &mut String) -> String {
+    let string = mem::take(string);
+    let mut lines: Vec<_> = string.trim().split('\n').map(str::trim).collect();
+    lines.dedup_by(|line, prev| line.is_empty() && prev.is_empty());
+    lines.join("\n")
+}
+
+fn prop_val_text(val: &PropVal) -> Option<String> {
+    match val {
+        PropVal::String(text) | PropVal::Text(text) => Some(text.clone()),
+        PropVal::Int(int) => Some(int.to_string()),
+        PropVal::Date(date) => Some(format!("{:04}-{:02}-{:02}", date.year, date.month, date.day)),
+        PropVal::Error(_) => None,
+    }
+}
+
+fn sorted(tags: &Tags) -> Vec<&String> {
+    let mut tags: Vec<_> = tags.iter().collect();
+    tags.sort();
+    tags
+}
+
+fn sorted_props(props: &Props) -> Vec<(&String, &PropVal)> {
+    let mut props: Vec<_> = props.iter().filter(|(key, _)| !key.starts_with("src-")).collect();
+    props.sort_by_key(|(key, _)| *key);
+    props
+}
+
+fn code_block_out(code: &CodeBlock) -> String {
+    if code.language == MATH_TAG && code.mode == CodeModeHint::Replace {
+        return format!("$${}$$", code.code);
+    }
+    let body = code.code.strip_suffix('\n').unwrap_or(&code.code);
+    if code.language == "html" && code.tags.contains(UNCONVERTED_TAG) {
+        return body.to_string();
+    }
+    if code.tags.contains("indented") && code.language.is_empty() && !body.trim().is_empty() {
+        return prefixed(body, "    ", "    ");
+    }
+    let mut info = code.language.clone();
+    if !info.is_empty() {
+        for tag in sorted(&code.tags) {
+            if tag != "indented" {
+                info.push(' ');
+                info.push_str(tag);
+            }
+        }
+    }
+    for (key, val) in sorted_props(&code.props) {
+        let Some(text) = prop_val_text(val) else { continue; };
+        info.push(' ');
+        if key == "highlight" {
+            let _ = write!(info, "{{{text}}}");
+        } else if text.is_empty() || text.contains([' ', ',', '"', '\'', '{', '}']) {
+            let text = text.replace('\\', "\\\\").replace('"', "\\\"");
+            let _ = write!(info, "{key}=\"{text}\"");
+        } else {
+            let _ = write!(info, "{key}={text}");
+        }
+    }
+    // the info string of a backtick fence can not have backticks
+    let c = if info.contains('`') { '~' } else { '`' };
+    let fence = c.to_string().repeat(longest_run(&code.code, c).max(2) + 1);
+    let mut res = format!("{fence}{}\n", info.trim_start());
+    if !code.code.is_empty() {
+        res.push_str(body);
+        res.push('\n');
+    }
+    res.push_str(&fence);
+    res
+}
+
+fn list_out(list: &List, alt: bool) -> String {
+    if list.tags.contains("deflist") {
+        return deflist_out(list);
+    }
+    let mut res = Vec::new();
+    for (i, item) in list.items.iter().enumerate() {
+        let bullet = if alt { '*' } else { '-' };
+        let marker = match list.ltype {
+            ListType::Identical => bullet.to_string(),
+            ListType::Distinct => format!("{}{}", i + 1, if alt { ')' } else { '.' }),
+            ListType::Checked => {
+                let check = if item.tags.contains("checked") { 'x' } else { ' ' };
+                format!("{bullet} [{check}]")
+            },
+        };
+        let mut blocks = Blocks::default();
+        paragraph_out(item, &mut blocks);
+        let content = blocks.join_tight();
+        if content.is_empty() {
+            res.push(marker);
+        } else {
+            // continuation lines line up with the content after the bullet or number
+            let width = if list.ltype == ListType::Checked { 1 } else { marker.len() };
+            res.push(prefixed(&content, &format!("{marker} "), &" ".repeat(width + 1)));
+        }
+    }
+    res.join("\n")
+}
+
+/// Items with line breaks in their text as spaces, for items that are written on one line, so
+/// that line starts are escaped where they end up.
+fn one_line(items: &[ParagraphItem]) -> Vec<ParagraphItem> {
+    items.iter().map(|item| match item {
+        ParagraphItem::Text(text) => ParagraphItem::Text(text.replace('\n', " ")),
+        ParagraphItem::Em(em) => ParagraphItem::Em(Emphasis {
+            text: em.text.replace('\n', " "),
+            ..em.clone()
+        }),
+        ParagraphItem::MText(mtext) => ParagraphItem::MText(TextWithMeta {
+            text: mtext.text.replace('\n', " "),
+            ..mtext.clone()
+        }),
+        item => item.clone(),
+    }).collect()
+}
+
+fn deflist_out(list: &List) -> String {
+    let mut res = String::new();
+    let mut after_term = false;
+    for item in &list.items {
+        let term = item.tags.contains("deflist-term");
+        let mut blocks = Blocks::default();
+        if term {
+            paragraph_out(&Paragraph { items: one_line(&item.items), ..item.clone() }, &mut blocks);
+        } else {
+            paragraph_out(item, &mut blocks);
+        }
+        let content = blocks.join("\n\n");
+        if term {
+            if !res.is_empty() {
+                res.push_str("\n\n");
+            }
+            res.push_str(&content.replace('\n', " "));
+            after_term = true;
+        } else if !after_term {
+            // a definition needs a term before it, which a heading in it may have split off
+            if !res.is_empty() && !content.is_empty() {
+                res.push_str("\n\n");
+            }
+            res.push_str(&content);
+        } else {
+            res.push('\n');
+            res.push_str(&prefixed(&content, ": ", "  "));
+        }
+    }
+    res
+}
+
+/// Escape pipes that are not escaped yet, such as those in code spans.
+fn table_cell_escaped(cell: &str) -> String {
+    let mut res = String::new();
+    let mut escaped = false;
+    for c in cell.chars() {
+        if c == '|' && !escaped {
+            res.push('\\');
+        }
+        escaped = c == '\\' && !escaped;
+        res.push(c);
+    }
+    res
+}
+
+fn table_out(table: &Table) -> String {
+    let columns = table.rows.iter().map(|row| row.items.len()).max().unwrap_or(0);
+    if columns == 0 {
+        return String::new();
+    }
+    let aligns: Vec<String> = match table.props.get("align") {
+        Some(PropVal::String(aligns)) => aligns.split(',').map(str::to_string).collect(),
+        _ => Vec::new(),
+    };
+    let mut lines = Vec::new();
+    for (i, row) in table.rows.iter().enumerate() {
+        let mut line = String::from("|");
+        for c in 0..columns {
+            let mut cell = String::new();
+            if let Some(par) = row.items.get(c) {
+                inline_items_out(&one_line(&par.items), &mut cell, |_, _| { });
+            }
+            let _ = write!(line, " {} |", table_cell_escaped(&cell.replace('\n', " ")).trim());
+        }
+        lines.push(line);
+        if i == 0 {
+            let mut line = String::from("|");
+            for c in 0..columns {
+                let delimiter = match aligns.get(c).map(String::as_str) {
+                    Some("left") => ":--",
+                    Some("center") => ":-:",
+                    Some("right") => "--:",
+                    _ => "---",
+                };
+                let _ = write!(line, " {delimiter} |");
+            }
+            lines.push(line);
+        }
+    }
+    lines.join("\n")
+}
+
+/// Whether the heading prop is generated from tagged text or a link in the heading.
+fn is_indexed_heading_prop(key: &str) -> bool {
+    key.rsplit_once('-').is_some_and(|(tag, index)| {
+        INDEXED_HEADING_PROPS.contains(&tag) && index.parse::<usize>().is_ok()
+    })
+}
+
+/// The link whose items start at the index in the heading, and the index after its items.
+fn heading_link(head: &Heading, start: usize) -> Option<(Link, usize)> {
+    let prop = |name: &str| head.props.get(&format!("{name}-{start}"));
+    let Some(PropVal::String(url)) = prop("link") else { return None; };
+    let end = match prop("link-end") {
+        Some(PropVal::Int(end)) => usize::try_from(*end).unwrap_or(start),
+        _ => start + 1,
+    }.clamp(start, head.items.len());
+    let mut link = Link {
+        url: url.clone(),
+        ..Default::default()
+    };
+    // the lines of a setext heading become one
+    for item in &head.items[start..end] {
+        link.items.push(match item {
+            EmOrText::Text(text) => EmOrText::Text(text.replace('\n', " ")),
+            EmOrText::Em(em) => EmOrText::Em(Emphasis {
+                text: em.text.replace('\n', " "),
+                ..em.clone()
+            }),
+        });
+    }
+    if let Some(PropVal::String(tags)) = prop("link-tags") {
+        link.tags = tags.split_whitespace().map(str::to_string).collect();
+    }
+    if let Some(title) = prop("link-title") {
+        link.props.insert("title".to_string(), title.clone());
+    }
+    Some((link, end))
+}
+
+fn heading_out(head: &Heading) -> String {
+    let level = usize::from(head.level.saturating_add(1).min(6));
+    let mut content = String::new();
+    let mut i = 0;
+    while i < head.items.len() {
+        if let Some((link, end)) = heading_link(head, i) {
+            link_out(&link, &mut content);
+            if end > i {
+                i = end;
+                continue;
+            }
+        }
+        let text_prop = |tag: &str| match head.props.get(&format!("{tag}-{i}")) {
+            Some(PropVal::String(text)) => Some(text.as_str()),
+            _ => None,
+        };
+        match &head.items[i] {
+            EmOrText::Text(text) if text_prop(CODE_TAG).is_some() => {
+                code_span_out(text, &mut content);
+            },
+            EmOrText::Text(text) if text_prop(MATH_TAG).is_some() => {
+                math_out(text, &mut content);
+            },
+            EmOrText::Text(text) => {
+                let mut tags = Tags::default();
+                for tag in ["super", "sub"] {
+                    if text_prop(tag).is_some() { tags.insert(tag.to_string()); }
+                }
+                marked_out(&text.replace('\n', " "), &tag_markers(&tags, false), &mut content);
+            },
+            EmOrText::Em(em) => {
+                em_out(&Emphasis { text: em.text.replace('\n', " "), ..em.clone() }, &mut content);
+            },
+        }
+        i += 1;
+    }
+    // an empty link after the last item
+    if let Some((link, _)) = heading_link(head, head.items.len()) {
+        link_out(&link, &mut content);
+    }
+    // spaces around the content are not part of a heading
+    let mut res = format!("{} {}", "#".repeat(level), content.trim_matches(' '));
+    // a trailing run of '#' after a space would be a closing sequence
+    let trimmed = res.trim_end_matches('#');
+    if trimmed.len() < res.len() && trimmed.len() > level && trimmed.ends_with(' ') {
+        res.insert(trimmed.len(), '\\');
+    }
+    // and a trailing '}' would close an attribute block
+    if res.ends_with('}') {
+        res.insert(res.len() - 1, '\\');
+    }
+    let mut attrs = Vec::new();
+    if let Some(PropVal::String(id)) = head.props.get("id") {
+        attrs.push(format!("#{id}"));
+    }
+    attrs.extend(sorted(&head.tags).into_iter().map(|tag| format!(".{tag}")));
+    for (key, val) in sorted_props(&head.props) {
+        if key == "id" || is_indexed_heading_prop(key) { continue; }
+        if let Some(text) = prop_val_text(val) && !text.contains(char::is_whitespace) {
+            attrs.push(format!("{key}={text}"));
+        }
+    }
+    if !attrs.is_empty() {
+        let _ = write!(res, " {{{}}}", attrs.join(" "));
+    }
+    res.trim_end().to_string()
+}
+
+fn section_items_out(items: &[SectionItem], blocks: &mut Blocks) {
+    for item in items {
+        match item {
+            SectionItem::Paragraph(par) => paragraph_out(par, blocks),
+            SectionItem::Section(section) => section_out(section, blocks),
+        }
+    }
+}
+
+fn section_out(section: &Section, blocks: &mut Blocks) {
+    let mut inner = Blocks::default();
+    section_items_out(&section.items, &mut inner);
+    let content = inner.join("\n\n");
+    if section.tags.contains("footnotes") {
+        for block in inner.blocks {
+            blocks.push(block);
+        }
+    } else if section.tags.contains("footnote-def") {
+        // collected footnotes show their number, the id still has the label
+        let label = match section.props.get("id") {
+            Some(PropVal::String(id)) if id.starts_with("footnote-") => id["footnote-".len()..]
+                .to_string(),
+            _ => plain_text(&section.heading.items),
+        };
+        blocks.push(prefixed(&content, &format!("[^{label}]: "), "    "));
+    } else if section.tags.contains("blockquote-typed") {
+        let qtype = match section.props.get("blockquote-type") {
+            Some(PropVal::String(qtype)) => qtype.to_uppercase(),
+            _ => plain_text(&section.heading.items).to_uppercase(),
+        };
+        // an empty list item can not interrupt the paragraph the type marker is parsed as
+        let first = content.lines().next().unwrap_or_default();
+        let empty_item = matches!(first, "-" | "*" | "+")
+            || first.strip_suffix(['.', ')']).is_some_and(|nr| nr.parse::<u32>().is_ok());
+        let sep = if empty_item { "\n\n" } else { "\n" };
+        let content = format!("[!{qtype}]{sep}{content}");
+        blocks.push(prefixed(&content, "> ", "> "));
+    } else if section.tags.contains("blockquote") {
+        if content.is_empty() {
+            blocks.push(">".to_string());
+        } else {
+            blocks.push(prefixed(&content, "> ", "> "));
+        }
+    } else if section.tags.contains("details") {
+        let open = if section.tags.contains("details-open") { " open" } else { "" };
+        let summary = plain_text(&section.heading.items)
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;");
+        blocks.push(format!("<details{open}>\n<summary>{summary}</summary>"));
+        for block in inner.blocks {
+            blocks.push(block);
+        }
+        blocks.push("</details>".to_string());
+    } else {
+        blocks.push(heading_out(&section.heading));
+        for block in inner.blocks {
+            blocks.push(block);
+        }
+    }
+}
+
+/// Write a YAML scalar, quoted if it would otherwise not be read back as the same string.
+fn yaml_string_out(text: &str, output: &mut String) {
+    let plain = !text.is_empty()
+        && text.trim() == text
+        && !text.starts_with(|c: char| "-?:,[]{}#&*!|>'\"%@`".contains(c))
+        && !text.contains(": ") && !text.contains(" #") && !text.contains('\n')
+        && !text.ends_with(':')
+        && !matches!(text, "~" | "null" | "Null" | "NULL")
+        && typed_prop_val(text) == PropVal::String(text.to_string());
+    if plain {
+        output.push_str(text);
+    } else {
+        output.push('"');
+        for c in text.chars() {
+            match c {
+                '"' | '\\' => { output.push('\\'); output.push(c); },
+                '\n' => output.push_str("\\n"),
+                '\t' => output.push_str("\\t"),
+                _ => output.push(c),
+            }
+        }
+        output.push('"');
+    }
+}
+
+fn nav_out(nav: &Nav, indent: usize, output: &mut String) {
+    for link in &nav.links {
+        let _ = write!(output, "{:indent$}- ", "");
+        yaml_string_out(&plain_text(&link.items), output);
+        output.push_str(": ");
+        yaml_string_out(&link.url, output);
+        output.push('\n');
+    }
+    for sub in &nav.subs {
+        let _ = write!(output, "{:indent$}- ", "");
+        yaml_string_out(&sub.description, output);
+        output.push_str(":\n");
+        nav_out(sub, indent + 4, output);
+    }
+}
+
+fn front_matter_out(doc: &Doc, output: &mut String) {
+    // link definitions are written after the document
+    let navs: Vec<_> = doc.navs.iter()
+        .filter(|nav| !nav.tags.contains("link-definitions"))
+        .collect();
+    if doc.tags.is_empty() && doc.props.is_empty() && navs.is_empty() {
+        return;
+    }
+    output.push_str("---\n");
+    if !doc.tags.is_empty() {
+        output.push_str("tags: [");
+        for (i, tag) in sorted(&doc.tags).into_iter().enumerate() {
+            if i > 0 { output.push_str(", "); }
+            yaml_string_out(tag, output);
+        }
+        output.push_str("]\n");
+    }
+    for (key, val) in sorted_props(&doc.props) {
+        let text = match val {
+            PropVal::String(text) | PropVal::Text(text) => {
+                let _ = write!(output, "{key}: ");
+                yaml_string_out(text, output);
+                output.push('\n');
+                continue;
+            },
+            val => prop_val_text(val),
+        };
+        if let Some(text) = text {
+            let _ = writeln!(output, "{key}: {text}");
+        }
+    }
+    for nav in navs {
+        output.push_str("nav:\n");
+        nav_out(nav, 2, output);
+    }
+    output.push_str("---\n\n");
+}
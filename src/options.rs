@@ -0,0 +1,284 @@
+use crate::MICRO_SECTION_HEADING_LEVEL;
+
+pub use pulldown_cmark::Options;
+
+use std::{ fmt, sync::Arc };
+
+/// How html blocks are mapped.
+#[derive(Clone, Copy, Default, Hash, Debug, Eq, PartialEq)]
+pub enum HtmlBlockPolicy {
+    /// Code block with language "html", tagged with the unconverted tag.
+    #[default] Code,
+    /// Map details, paragraphs, tables, images, links, kbd and line breaks;
+    /// blocks with any other html become code blocks like with `Code`.
+    Map,
+    /// Raw html as plain paragraph text.
+    Text,
+    /// Discard html blocks.
+    Drop,
+}
+
+/// How inline html tags are mapped.
+#[derive(Clone, Copy, Default, Hash, Debug, Eq, PartialEq)]
+pub enum InlineHtmlPolicy {
+    /// Map formatting, code, kbd, line break, link and image tags;
+    /// enclose the text inside other tags in a deemphasised "html(" ... ")" enclosure.
+    #[default] Enclose,
+    /// Discard the tags, keep the text inside of them.
+    Strip,
+}
+
+/// Turns wikilink page names into urls; `None` means the page is unknown.
+///
+/// Resolvers compare equal only if they are the same instance.
+#[derive(Clone)]
+pub struct WikilinkResolver(Arc<ResolveFn>);
+
+type ResolveFn = dyn Fn(&str) -> Option<String> + Send + Sync;
+
+impl WikilinkResolver {
+    pub fn new(resolve: impl Fn(&str) -> Option<String> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(resolve))
+    }
+
+    pub fn resolve(&self, page: &str) -> Option<String> {
+        (self.0)(page)
+    }
+}
+
+impl fmt::Debug for WikilinkResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("WikilinkResolver(..)")
+    }
+}
+
+impl PartialEq for WikilinkResolver {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for WikilinkResolver { }
+
+/// Rewrites link and image urls.
+///
+/// Rewriters compare equal only if they are the same instance.
+#[derive(Clone)]
+pub struct LinkRewriter(Arc<RewriteFn>);
+
+type RewriteFn = dyn Fn(&str) -> String + Send + Sync;
+
+impl LinkRewriter {
+    pub fn new(rewrite: impl Fn(&str) -> String + Send + Sync + 'static) -> Self {
+        Self(Arc::new(rewrite))
+    }
+
+    pub fn rewrite(&self, url: &str) -> String {
+        (self.0)(url)
+    }
+}
+
+impl fmt::Debug for LinkRewriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("LinkRewriter(..)")
+    }
+}
+
+impl PartialEq for LinkRewriter {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for LinkRewriter { }
+
+/// Options to steer the conversion.
+///
+/// The default is the preset used by [`parse_md_to_incodoc`](crate::parse_md_to_incodoc).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ConvertOptions {
+    /// Markdown extensions enabled in the parser.
+    pub extensions: Options,
+    /// Tag for inline code.
+    pub code_tag: String,
+    /// Tag for inline math and language of display math.
+    pub math_tag: String,
+    /// Tag for corporeal content that is kept unconverted.
+    pub unconverted_tag: String,
+    /// Heading level base of micro sections: block quotes and footnote definitions.
+    pub micro_section_heading_level: u8,
+    pub html_blocks: HtmlBlockPolicy,
+    pub inline_html: InlineHtmlPolicy,
+    /// Add "src-start" and "src-end" byte offset props to paragraphs, sections, headings,
+    /// code blocks, lists, tables and links.
+    pub source_positions: bool,
+    /// With source positions, also add "src-line" and "src-column" props of the start.
+    /// Both count from 1; the column counts characters.
+    pub line_columns: bool,
+    /// Give every heading without explicit id a GitHub compatible "id" prop and tag links to
+    /// anchors in the document that do not exist "broken-anchor".
+    pub heading_ids: bool,
+    /// Resolver for the targets of wikilinks, which are tagged "wikilink".
+    /// Without one the page name is the url; unresolved wikilinks are tagged
+    /// "unresolved-wikilink".
+    pub wikilink_resolver: Option<WikilinkResolver>,
+    /// Move all footnote definitions into one section tagged "footnotes" at the end of the
+    /// document, numbered in order of first reference. References get an "id" prop that the
+    /// "footnote-backref" links at the end of each definition point to. References without
+    /// definition are not numbered.
+    pub collect_footnotes: bool,
+    /// Keep the link reference definitions in a nav tagged "link-definitions", one link per
+    /// definition with the label as text.
+    pub link_definitions: bool,
+    /// Give links and images a "link-type" prop with their markdown syntax: "inline",
+    /// "reference", "collapsed", "shortcut" or "autolink".
+    pub link_types: bool,
+    /// Extensions of relative link and image paths to replace, without dot, first match wins:
+    /// ("md", "incodoc") turns "../api/foo.md#bar" into "../api/foo.incodoc#bar".
+    pub link_extensions: Vec<(String, String)>,
+    /// Url that relative link and image urls are resolved against, after the extension mapping.
+    /// Bare fragments are kept.
+    pub base_url: Option<String>,
+    /// Rewriter that gets every link and image url last, including those of navs.
+    pub link_rewriter: Option<LinkRewriter>,
+}
+
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        Self {
+            extensions: Options::all(),
+            code_tag: "code".to_string(),
+            math_tag: "latex-math".to_string(),
+            unconverted_tag: "unconv-corp".to_string(),
+            micro_section_heading_level: MICRO_SECTION_HEADING_LEVEL,
+            html_blocks: HtmlBlockPolicy::default(),
+            inline_html: InlineHtmlPolicy::default(),
+            source_positions: false,
+            line_columns: false,
+            heading_ids: false,
+            wikilink_resolver: None,
+            collect_footnotes: false,
+            link_definitions: false,
+            link_types: false,
+            link_extensions: Vec::new(),
+            base_url: None,
+            link_rewriter: None,
+        }
+    }
+}
+
+impl ConvertOptions {
+    #[must_use]
+    pub fn extensions(mut self, extensions: Options) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    /// Enable or disable one or more extensions.
+    #[must_use]
+    pub fn extension(mut self, extension: Options, enabled: bool) -> Self {
+        self.extensions.set(extension, enabled);
+        self
+    }
+
+    #[must_use]
+    pub fn code_tag(mut self, tag: impl Into<String>) -> Self {
+        self.code_tag = tag.into();
+        self
+    }
+
+    #[must_use]
+    pub fn math_tag(mut self, tag: impl Into<String>) -> Self {
+        self.math_tag = tag.into();
+        self
+    }
+
+    #[must_use]
+    pub fn unconverted_tag(mut self, tag: impl Into<String>) -> Self {
+        self.unconverted_tag = tag.into();
+        self
+    }
+
+    #[must_use]
+    pub fn micro_section_heading_level(mut self, level: u8) -> Self {
+        self.micro_section_heading_level = level;
+        self
+    }
+
+    #[must_use]
+    pub fn html_blocks(mut self, policy: HtmlBlockPolicy) -> Self {
+        self.html_blocks = policy;
+        self
+    }
+
+    #[must_use]
+    pub fn inline_html(mut self, policy: InlineHtmlPolicy) -> Self {
+        self.inline_html = policy;
+        self
+    }
+
+    #[must_use]
+    pub fn source_positions(mut self, enabled: bool) -> Self {
+        self.source_positions = enabled;
+        self
+    }
+
+    #[must_use]
+    pub fn line_columns(mut self, enabled: bool) -> Self {
+        self.line_columns = enabled;
+        self
+    }
+
+    #[must_use]
+    pub fn heading_ids(mut self, enabled: bool) -> Self {
+        self.heading_ids = enabled;
+        self
+    }
+
+    #[must_use]
+    pub fn wikilink_resolver(
+        mut self, resolve: impl Fn(&str) -> Option<String> + Send + Sync + 'static
+    ) -> Self {
+        self.wikilink_resolver = Some(WikilinkResolver::new(resolve));
+        self
+    }
+
+    #[must_use]
+    pub fn collect_footnotes(mut self, enabled: bool) -> Self {
+        self.collect_footnotes = enabled;
+        self
+    }
+
+    #[must_use]
+    pub fn link_definitions(mut self, enabled: bool) -> Self {
+        self.link_definitions = enabled;
+        self
+    }
+
+    #[must_use]
+    pub fn link_types(mut self, enabled: bool) -> Self {
+        self.link_types = enabled;
+        self
+    }
+
+    /// Add an extension mapping, see [`link_extensions`](Self::link_extensions).
+    #[must_use]
+    pub fn link_extension(mut self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        self.link_extensions.push((from.into(), to.into()));
+        self
+    }
+
+    #[must_use]
+    pub fn base_url(mut self, url: impl Into<String>) -> Self {
+        self.base_url = Some(url.into());
+        self
+    }
+
+    #[must_use]
+    pub fn link_rewriter(
+        mut self, rewrite: impl Fn(&str) -> String + Send + Sync + 'static
+    ) -> Self {
+        self.link_rewriter = Some(LinkRewriter::new(rewrite));
+        self
+    }
+}
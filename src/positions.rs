@@ -0,0 +1,94 @@
+use incodoc::*;
+
+use std::ops::Range;
+
+/// Marks nodes with the markdown source range they originate from.
+pub(crate) struct SourceMap<'a> {
+    input: &'a str,
+    enabled: bool,
+    line_starts: Option<Vec<usize>>,
+}
+
+fn get_int(props: &Props, key: &str) -> Option<i64> {
+    match props.get(key) {
+        Some(PropVal::Int(int)) => Some(*int),
+        _ => None,
+    }
+}
+
+fn to_int(offset: usize) -> i64 {
+    i64::try_from(offset).unwrap_or(i64::MAX)
+}
+
+impl<'a> SourceMap<'a> {
+    pub(crate) fn new(input: &'a str, enabled: bool, line_columns: bool) -> Self {
+        let line_starts = (enabled && line_columns).then(|| {
+            let mut starts = vec![0];
+            starts.extend(input.match_indices('\n').map(|(i, _)| i + 1));
+            starts
+        });
+        Self { input, enabled, line_starts }
+    }
+
+    /// Mark props with the range, or grow the range already in them to also cover this one.
+    pub(crate) fn mark(&self, props: &mut Props, range: &Range<usize>) {
+        if !self.enabled { return; }
+        let start = to_int(range.start);
+        let end = to_int(range.end);
+        match get_int(props, "src-start") {
+            Some(old) if old <= start => { },
+            _ => {
+                props.insert("src-start".to_string(), PropVal::Int(start));
+                self.mark_line_column(props, range.start);
+            },
+        }
+        if get_int(props, "src-end").is_none_or(|old| old < end) {
+            props.insert("src-end".to_string(), PropVal::Int(end));
+        }
+    }
+
+    fn mark_line_column(&self, props: &mut Props, offset: usize) {
+        let Some(line_starts) = &self.line_starts else { return; };
+        let line = line_starts.partition_point(|start| *start <= offset).max(1);
+        let line_start = line_starts[line - 1];
+        let column = self.input.get(line_start..offset).map_or(0, |s| s.chars().count()) + 1;
+        props.insert("src-line".to_string(), PropVal::Int(to_int(line)));
+        props.insert("src-column".to_string(), PropVal::Int(to_int(column)));
+    }
+
+    /// Give sections built from headings the range of their heading and content.
+    pub(crate) fn mark_sections(&self, doc: &mut Doc) {
+        if !self.enabled { return; }
+        for item in &mut doc.items {
+            if let DocItem::Section(section) = item {
+                self.mark_section(section);
+            }
+        }
+    }
+
+    fn mark_section(&self, section: &mut Section) {
+        let mut ranges = Vec::new();
+        ranges.push(props_range(&section.heading.props));
+        for item in &mut section.items {
+            match item {
+                SectionItem::Section(sub) => {
+                    self.mark_section(sub);
+                    ranges.push(props_range(&sub.props));
+                },
+                SectionItem::Paragraph(par) => ranges.push(props_range(&par.props)),
+            }
+        }
+        if section.props.contains_key("src-start") {
+            return;
+        }
+        for range in ranges.into_iter().flatten() {
+            self.mark(&mut section.props, &range);
+        }
+    }
+}
+
+fn props_range(props: &Props) -> Option<Range<usize>> {
+    let start = usize::try_from(get_int(props, "src-start")?).ok()?;
+    let end = usize::try_from(get_int(props, "src-end")?).ok()?;
+    Some(start..end)
+}
@@ -0,0 +1,335 @@
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod prop_tests {
+    //! Property tests on random markdown generated from the supported constructs.
+
+    use crate::*;
+
+    use proptest::prelude::*;
+    use pulldown_cmark::{ Parser, Event, Tag, TagEnd };
+
+    fn word() -> impl Strategy<Value = String> {
+        prop_oneof![
+            4 => "[a-zA-Z0-9]{1,8}",
+            1 => prop::sample::select(vec![
+                "&", "&amp;", "*", "_", "1.", "2)", "#", "<", ">", "|", "\\", "`", "$", "~", "^",
+                "[", "]", "-", "+", "=", ":", "!", "{", "}", "(", ")", "\"", "'",
+            ]).prop_map(str::to_string),
+        ]
+    }
+
+    fn words() -> impl Strategy<Value = String> {
+        prop::collection::vec("[a-zA-Z0-9]{1,8}", 1..4).prop_map(|words| words.join(" "))
+    }
+
+    /// Punctuation, or inline content that starts and ends with it, to put next to emphasis.
+    fn punctuation() -> impl Strategy<Value = String> {
+        prop::sample::select(vec![
+            "", "", "(", ")", ".", "!", "?", "&amp;", "$", "'", "\"", "-", ":", "*", "_", "`",
+            "<http://a.b>", "![i](p.png)",
+        ]).prop_map(str::to_string)
+    }
+
+    /// Emphasis that may start or end with punctuation.
+    fn emphasis() -> impl Strategy<Value = String> {
+        let markers = prop::sample::select(vec![
+            ("*", "*"), ("**", "**"), ("***", "***"), ("_", "_"), ("__", "__"), ("~~", "~~"),
+            ("<b>", "</b>"), ("<i>", "</i>"),
+        ]);
+        let leaf = (markers.clone(), punctuation(), words(), punctuation())
+            .prop_map(|((open, close), p, w, q)| format!("{open}{p}{w}{q}{close}"));
+        leaf.prop_recursive(2, 4, 2, move |inner| {
+            (markers.clone(), punctuation(), inner, punctuation())
+                .prop_map(|((open, close), p, e, q)| format!("{open}{p}{e}{q}{close}"))
+        })
+    }
+
+    /// Emphasis next to punctuation and other emphasis, without spaces in between.
+    fn adjacent_emphasis() -> impl Strategy<Value = String> {
+        (prop::collection::vec((punctuation(), emphasis()), 1..4), punctuation())
+            .prop_map(|(items, last)| {
+                items.into_iter().flat_map(|(p, e)| [p, e]).chain([last]).collect()
+            })
+    }
+
+    fn inline() -> impl Strategy<Value = String> {
+        prop_oneof![
+            8 => word(),
+            2 => adjacent_emphasis(),
+            1 => words().prop_map(|w| format!("*{w}*")),
+            1 => words().prop_map(|w| format!("**{w}**")),
+            1 => words().prop_map(|w| format!("***{w}***")),
+            1 => words().prop_map(|w| format!("~~{w}~~")),
+            1 => "[a-z]{1,4}".prop_map(|w| format!("^{w}^")),
+            1 => "[a-z]{1,4}".prop_map(|w| format!("~{w}~")),
+            1 => "[a-z*_|$ ]{1,8}".prop_map(|w| format!("`{w}`")),
+            1 => "[a-z+^]{1,8}".prop_map(|w| format!("${w}$")),
+            1 => (words(), "[a-z]{1,8}").prop_map(|(w, u)| format!("[{w}](http://{u}.org)")),
+            1 => (words(), "[a-z]{1,8}").prop_map(|(w, u)| format!("![{w}]({u}.png \"{w}\")")),
+            1 => "[a-z]{1,8}".prop_map(|u| format!("<https://{u}.org>")),
+            1 => "[a-z]{1,8}".prop_map(|w| format!("[[{w}]]")),
+            1 => "[1-3]".prop_map(|n| format!("[^{n}]")),
+            1 => (prop::sample::select(vec!["b", "i", "sup", "kbd", "code"]), words())
+                .prop_map(|(tag, w)| format!("<{tag}>{w}</{tag}>")),
+            // never closed
+            1 => (prop::sample::select(vec!["b", "i", "sup", "kbd", "code"]), words())
+                .prop_map(|(tag, w)| format!("<{tag}>{w}")),
+            1 => (words(), "[a-z]{1,8}").prop_map(|(w, u)| format!("<a href=\"{u}.org\">{w}</a>")),
+            1 => prop::sample::select(vec!["<br>", "<br/>", "<img src=\"a.png\" alt=\"a\">"])
+                .prop_map(str::to_string),
+        ]
+    }
+
+    /// Line that may start with anything, such as a heading or list marker.
+    fn free_line() -> impl Strategy<Value = String> {
+        prop::collection::vec(inline(), 1..6).prop_map(|items| items.join(" "))
+    }
+
+    /// Line that starts with a word, so it can not start a block.
+    /// Headings inside containers can not be converted, so only top level lines are free.
+    fn line() -> impl Strategy<Value = String> {
+        ("[a-zA-Z]{1,8}", prop::collection::vec(inline(), 0..5))
+            .prop_map(|(first, items)| [vec![first], items].concat().join(" "))
+    }
+
+    fn paragraph() -> impl Strategy<Value = String> {
+        prop::collection::vec(line(), 1..3).prop_map(|lines| lines.join("\n"))
+    }
+
+    fn free_paragraph() -> impl Strategy<Value = String> {
+        prop::collection::vec(free_line(), 1..3).prop_map(|lines| lines.join("\n"))
+    }
+
+    fn list() -> impl Strategy<Value = String> {
+        let item = prop_oneof![line().prop_map(|l| vec![l])];
+        let leaf = (
+            prop::sample::select(vec!["- ", "* ", "1. ", "- [ ] ", "- [x] "]),
+            prop::collection::vec(item, 1..4),
+        ).prop_map(|(marker, items)| {
+            items.into_iter()
+                .map(|lines| format!("{marker}{}", lines.join("\n  ")))
+                .collect::<Vec<_>>()
+                .join("\n")
+        });
+        leaf.prop_recursive(2, 8, 3, |inner| {
+            (line(), inner).prop_map(|(first, nested)| {
+                let nested = nested.replace('\n', "\n   ");
+                format!("1. {first}\n   {nested}")
+            })
+        })
+    }
+
+    fn block() -> impl Strategy<Value = String> {
+        prop_oneof![
+            6 => free_paragraph(),
+            2 => (1..7usize, free_line()).prop_map(|(level, l)| format!("{} {l}", "#".repeat(level))),
+            2 => list(),
+            1 => ("[a-z]{0,5}", prop::collection::vec("[a-z`{}();= ]{0,12}", 0..3))
+                .prop_map(|(lang, lines)| format!("```{lang}\n{}\n```", lines.join("\n"))),
+            1 => prop::collection::vec("[a-z]{1,8}", 1..3)
+                .prop_map(|lines| format!("    {}", lines.join("\n    "))),
+            1 => paragraph().prop_map(|p| format!("> {}", p.replace('\n', "\n> "))),
+            1 => (prop::sample::select(vec!["NOTE", "TIP", "WARNING"]), paragraph())
+                .prop_map(|(kind, p)| format!("> [!{kind}]\n> {}", p.replace('\n', "\n> "))),
+            1 => (1..4usize, prop::collection::vec(line(), 1..6)).prop_map(|(columns, cells)| {
+                let row = |cells: &[String]| format!("| {} |", cells.join(" | "));
+                let cells: Vec<_> = cells.iter().map(|c| c.replace('|', "\\|")).collect();
+                let mut rows = vec![row(&cells[..columns.min(cells.len())])];
+                rows.push(format!("|{}", ":--|".repeat(columns.min(cells.len()))));
+                rows.extend(cells.chunks(columns).skip(1).map(row));
+                rows.join("\n")
+            }),
+            1 => (words(), line()).prop_map(|(term, def)| format!("{term}\n: {def}")),
+            1 => ("[1-3]", line()).prop_map(|(n, l)| format!("[^{n}]: {l}")),
+            1 => (words(), "[1-9]{1,3}%?", words()).prop_map(|(alt, width, caption)| {
+                format!("![{alt}](a.png){{width={width}}}\n\n*{caption}*")
+            }),
+            1 => Just("$$\nx^2\n$$".to_string()),
+            1 => prop::sample::select(vec![
+                "<details>\n<summary>a</summary>", "</details>", "<details open>", "<br>",
+                "<p align=\"center\">\n<img src=\"a.png\" alt=\"a\">\n</p>",
+                "<table>\n<tr><td><kbd>a</kbd></td></tr>\n</table>", "<div>\na\n</div>",
+            ]).prop_map(str::to_string),
+        ]
+    }
+
+    fn markdown() -> impl Strategy<Value = String> {
+        prop::collection::vec(block(), 0..8).prop_map(|blocks| blocks.join("\n\n"))
+    }
+
+    /// Text content of the markdown as pulldown-cmark sees it.
+    fn md_text(input: &str) -> String {
+        let mut text = String::new();
+        let mut in_metadata = false;
+        for event in Parser::new_ext(input, ConvertOptions::default().extensions) {
+            match event {
+                Event::Start(Tag::MetadataBlock(_)) => in_metadata = true,
+                Event::End(TagEnd::MetadataBlock(_)) => in_metadata = false,
+                Event::Text(t) | Event::Code(t) | Event::InlineMath(t) | Event::DisplayMath(t)
+                    if !in_metadata => text.push_str(&t),
+                // footnote references and definitions show their label
+                Event::FootnoteReference(label) | Event::Start(Tag::FootnoteDefinition(label)) => {
+                    text.push_str(&label);
+                },
+                // the alt text of an html image is its text
+                Event::InlineHtml(html) => {
+                    let tokens = html::tokens(&html);
+                    if let Some([html::Token::Open { name, attrs }]) = tokens.as_deref()
+                        && name == "img"
+                    {
+                        text.push_str(html::attr(attrs, "alt").unwrap_or_default());
+                    }
+                },
+                _ => { },
+            }
+        }
+        text
+    }
+
+    fn em_or_text_text(items: &[EmOrText], text: &mut String) {
+        for item in items {
+            match item {
+                EmOrText::Text(t) => text.push_str(t),
+                EmOrText::Em(em) => text.push_str(&em.text),
+            }
+        }
+    }
+
+    fn paragraph_text(par: &Paragraph, text: &mut String) {
+        for item in &par.items {
+            match item {
+                ParagraphItem::Text(t) => text.push_str(t),
+                ParagraphItem::MText(mtext) => text.push_str(&mtext.text),
+                ParagraphItem::Em(em) => text.push_str(&em.text),
+                // html is not text to pulldown-cmark
+                ParagraphItem::Code(Ok(code)) if code.tags.contains("unconv-corp") => { },
+                ParagraphItem::Code(Ok(code)) => text.push_str(&code.code),
+                ParagraphItem::Code(Err(_)) => { },
+                ParagraphItem::Link(link) => {
+                    em_or_text_text(&link.items, text);
+                    // image attributes are props
+                    for key in ["width", "height"] {
+                        match link.props.get(key) {
+                            Some(PropVal::Int(val)) => text.push_str(&format!("{{{key}={val}}}")),
+                            Some(PropVal::String(val)) => {
+                                text.push_str(&format!("{{{key}={val}}}"));
+                            },
+                            _ => { },
+                        }
+                    }
+                },
+                ParagraphItem::List(list) => {
+                    for item in &list.items {
+                        paragraph_text(item, text);
+                    }
+                },
+                ParagraphItem::Table(table) => {
+                    for row in &table.rows {
+                        for cell in &row.items {
+                            paragraph_text(cell, text);
+                        }
+                    }
+                },
+            }
+        }
+        if let Some(PropVal::String(caption)) = par.props.get("caption") {
+            text.push_str(caption);
+        }
+    }
+
+    fn section_text(section: &Section, text: &mut String) {
+        // the heading of a typed block quote is its type
+        if !section.tags.contains("blockquote-typed") {
+            em_or_text_text(&section.heading.items, text);
+        }
+        for item in &section.items {
+            match item {
+                SectionItem::Paragraph(par) => paragraph_text(par, text),
+                SectionItem::Section(section) => section_text(section, text),
+            }
+        }
+    }
+
+    /// Text content of the document.
+    fn doc_text(doc: &Doc) -> String {
+        let mut text = String::new();
+        for item in &doc.items {
+            match item {
+                DocItem::Paragraph(par) => paragraph_text(par, &mut text),
+                DocItem::Section(section) => section_text(section, &mut text),
+            }
+        }
+        text
+    }
+
+    fn without_whitespace(text: &str) -> String {
+        text.chars().filter(|c| !c.is_whitespace()).collect()
+    }
+
+    /// Deeply nested containers.
+    fn nested() -> impl Strategy<Value = String> {
+        let prefix = prop::sample::select(vec![
+            "> ", "- ", "1. ", "[^1]: ", ": ", "# ", "<details>\n\n",
+        ]);
+        (prop::collection::vec(prefix, 0..300), free_paragraph()).prop_map(|(prefixes, par)| {
+            format!("{}{par}\n{}{par}", prefixes.concat(), prefixes[..prefixes.len() / 2].concat())
+        })
+    }
+
+    /// Convert and write the document as incodoc and markdown, which all must not panic.
+    fn no_panic(input: &str) {
+        for opts in [
+            ConvertOptions::default(),
+            ConvertOptions::default()
+                .micro_section_heading_level(0)
+                .heading_ids(true)
+                .html_blocks(HtmlBlockPolicy::Map)
+                .collect_footnotes(true),
+            ConvertOptions::default()
+                .micro_section_heading_level(u8::MAX)
+                .source_positions(true)
+                .link_definitions(true)
+                .link_types(true)
+                .link_extension("md", "incodoc")
+                .base_url("https://example.org/docs/"),
+        ] {
+            let doc = parse_md_to_incodoc_with(input, &opts);
+            let _ = doc_to_incodoc(&doc);
+            let _ = doc_to_md(&doc);
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(512))]
+
+        #[test]
+        fn p_no_panic_arbitrary(input in "\\PC{0,200}") {
+            no_panic(&input);
+        }
+
+        #[test]
+        fn p_no_panic(input in markdown()) {
+            prop_assert!(convert(&input, &ConvertOptions::default()).is_ok());
+            no_panic(&input);
+        }
+
+        #[test]
+        fn p_no_panic_nested(input in nested()) {
+            no_panic(&input);
+        }
+
+        #[test]
+        fn p_idempotent(input in markdown()) {
+            let md = doc_to_md(&parse_md_to_incodoc(&input));
+            let again = doc_to_md(&parse_md_to_incodoc(&md));
+            prop_assert_eq!(again, md);
+        }
+
+        #[test]
+        fn p_no_text_lost(input in markdown()) {
+            let doc = parse_md_to_incodoc(&input);
+            let (md, doc) = (md_text(&input), doc_text(&doc));
+            prop_assert_eq!(without_whitespace(&doc), without_whitespace(&md));
+        }
+    }
+}
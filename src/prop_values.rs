@@ -0,0 +1,54 @@
+use incodoc::*;
+
+/// Remove double or single quotes. Returns if the text was quoted.
+pub(crate) fn unquote(text: &str) -> (String, bool) {
+    let text = text.trim();
+    if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') {
+        let mut res = String::new();
+        let mut chars = text[1..text.len() - 1].chars();
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                match chars.next() {
+                    Some('n') => res.push('\n'),
+                    Some('t') => res.push('\t'),
+                    Some(other) => res.push(other),
+                    None => { },
+                }
+            } else {
+                res.push(c);
+            }
+        }
+        (res, true)
+    } else if text.len() >= 2 && text.starts_with('\'') && text.ends_with('\'') {
+        (text[1..text.len() - 1].replace("''", "'"), true)
+    } else {
+        (text.to_string(), false)
+    }
+}
+
+/// Prop value of an unquoted metadata value: an int, a date or a string.
+/// Incodoc has no float value, so floats stay strings.
+pub(crate) fn typed_prop_val(text: &str) -> PropVal {
+    if let Ok(int) = text.parse::<i64>() {
+        return PropVal::Int(int);
+    }
+    if let Some(date) = parse_date(text) {
+        return PropVal::Date(date);
+    }
+    PropVal::String(text.to_string())
+}
+
+/// Parse `YYYY-MM-DD` or `YYYY/MM/DD`, optionally followed by a time.
+pub(crate) fn parse_date(text: &str) -> Option<Date> {
+    let date = text.split(['T', ' ']).next()?;
+    let sep = if date.contains('-') { '-' } else { '/' };
+    let mut parts = date.split(sep);
+    let (y, m, d) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some() || y.len() != 4 || m.len() != 2 || d.len() != 2 {
+        return None;
+    }
+    if !date.chars().all(|c| c.is_ascii_digit() || c == sep) {
+        return None;
+    }
+    Date::new(y.parse().ok()?, m.parse().ok()?, d.parse().ok()?).ok()
+}
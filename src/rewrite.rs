@@ -0,0 +1,141 @@
+//! Rewriting link and image urls: extension mapping, base url joining and a custom rewriter.
+
+use crate::ConvertOptions;
+
+use incodoc::*;
+
+/// Whether the url starts with a scheme, like "https:" or "mailto:".
+fn has_scheme(url: &str) -> bool {
+    let Some((scheme, _)) = url.split_once(':') else { return false; };
+    scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+}
+
+/// Whether the url is relative to the document: no scheme, host or bare fragment.
+fn is_relative(url: &str) -> bool {
+    !url.is_empty() && !url.starts_with('#') && !url.starts_with("//") && !has_scheme(url)
+}
+
+/// Split off the query and fragment.
+fn split_suffix(url: &str) -> (&str, &str) {
+    url.split_at(url.find(['?', '#']).unwrap_or(url.len()))
+}
+
+/// Replace the first matching extension of the path, keeping query and fragment.
+fn map_extension(url: &str, extensions: &[(String, String)]) -> Option<String> {
+    let (path, suffix) = split_suffix(url);
+    extensions.iter().find_map(|(from, to)| {
+        let stem = path.strip_suffix(from.as_str())?.strip_suffix('.')?;
+        (!stem.is_empty() && !stem.ends_with('/')).then(|| format!("{stem}.{to}{suffix}"))
+    })
+}
+
+/// Resolve "." and ".." segments. Relative paths keep the ".." segments they cannot resolve.
+fn remove_dot_segments(path: &str) -> String {
+    let absolute = path.starts_with('/');
+    let segments: Vec<_> = path.strip_prefix('/').unwrap_or(path).split('/').collect();
+    let mut res: Vec<&str> = Vec::new();
+    for (i, segment) in segments.iter().enumerate() {
+        let last = i + 1 == segments.len();
+        match *segment {
+            "." => { },
+            ".." if res.last().is_some_and(|prev| *prev != "..") => { res.pop(); },
+            ".." if absolute => { },
+            segment => res.push(segment),
+        }
+        if last && matches!(*segment, "." | "..") {
+            res.push("");
+        }
+    }
+    let joined = res.join("/");
+    if absolute { format!("/{joined}") } else { joined }
+}
+
+/// Resolve a relative url against the base url.
+fn join(base: &str, url: &str) -> String {
+    let base = base.split('#').next().unwrap_or_default();
+    // scheme and authority, then the path
+    let path_start = match base.split_once(':') {
+        Some((scheme, rest)) if has_scheme(base) => {
+            let start = scheme.len() + 1;
+            match rest.strip_prefix("//") {
+                Some(rest) => start + 2 + rest.find(['/', '?']).unwrap_or(rest.len()),
+                None => start,
+            }
+        },
+        _ => 0,
+    };
+    let (prefix, path) = base.split_at(path_start);
+    let path = path.split('?').next().unwrap_or_default();
+    if url.starts_with('?') {
+        return format!("{prefix}{path}{url}");
+    }
+    let merged = if url.starts_with('/') {
+        url.to_string()
+    } else {
+        let dir = path.rfind('/').map_or("", |end| &path[..=end]);
+        let dir = if dir.is_empty() && !prefix.is_empty() { "/" } else { dir };
+        format!("{dir}{url}")
+    };
+    let (path, suffix) = split_suffix(&merged);
+    format!("{prefix}{}{suffix}", remove_dot_segments(path))
+}
+
+fn rewritten(url: &str, email: bool, opts: &ConvertOptions) -> String {
+    let mut url = url.to_string();
+    if !email && is_relative(&url) {
+        if let Some(mapped) = map_extension(&url, &opts.link_extensions) {
+            url = mapped;
+        }
+        if let Some(base) = &opts.base_url {
+            url = join(base, &url);
+        }
+    }
+    match &opts.link_rewriter {
+        Some(rewriter) => rewriter.rewrite(&url),
+        None => url,
+    }
+}
+
+fn rewrite_heading_links(section: &mut Section, opts: &ConvertOptions) {
+    let props = &mut section.heading.props;
+    let starts: Vec<_> = props.keys()
+        .filter_map(|key| key.strip_prefix("link-")?.parse::<usize>().ok())
+        .collect();
+    for start in starts {
+        let email = matches!(
+            props.get(&format!("link-tags-{start}")),
+            Some(PropVal::String(tags)) if tags.split(' ').any(|tag| tag == "email-address")
+        );
+        if let Some(PropVal::String(url)) = props.get_mut(&format!("link-{start}")) {
+            *url = rewritten(url, email, opts);
+        }
+    }
+    for item in &mut section.items {
+        if let SectionItem::Section(sub) = item {
+            rewrite_heading_links(sub, opts);
+        }
+    }
+}
+
+/// Rewrite the urls of all links, images and navs, of html images in links and of links in
+/// headings.
+/// Relative urls get their extension mapped and are joined to the base url, in that order;
+/// the rewriter, if any, gets every url last.
+pub(crate) fn rewrite_links(doc: &mut Doc, opts: &ConvertOptions) {
+    if opts.link_extensions.is_empty() && opts.base_url.is_none() && opts.link_rewriter.is_none() {
+        return;
+    }
+    for link in doc.links_mut(true) {
+        link.url = rewritten(&link.url, link.tags.contains("email-address"), opts);
+        // the url of an html image inside the link
+        if let Some(PropVal::String(image)) = link.props.get_mut("image") {
+            *image = rewritten(image, false, opts);
+        }
+    }
+    for item in &mut doc.items {
+        if let DocItem::Section(section) = item {
+            rewrite_heading_links(section, opts);
+        }
+    }
+}
@@ -0,0 +1,374 @@
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod spec_tests {
+    //! Structural comparison against the vendored CommonMark and GFM spec examples.
+    //! Every construct in the expected html of an example needs a counterpart in the document.
+    //! Run `cargo test spec -- --nocapture` to see which spec sections are lossy and why.
+
+    use crate::*;
+
+    use std::{ collections::BTreeMap, mem };
+
+    const FENCE: &str = "````````````````````````````````";
+
+    struct Example {
+        number: usize,
+        section: String,
+        markdown: String,
+        html: String,
+    }
+
+    /// Examples of a spec file, numbered in order like the spec does.
+    fn examples(spec: &str) -> Vec<Example> {
+        let mut examples = Vec::new();
+        let mut section = String::new();
+        let mut lines = spec.lines();
+        while let Some(line) = lines.next() {
+            if let Some(heading) = line.strip_prefix("## ") {
+                section = heading.to_string();
+            } else if line == format!("{FENCE} example") {
+                let (mut markdown, mut html) = (String::new(), String::new());
+                let mut in_html = false;
+                for line in lines.by_ref().take_while(|line| *line != FENCE) {
+                    if line == "." && !in_html {
+                        in_html = true;
+                    } else if in_html {
+                        html.push_str(line);
+                        html.push('\n');
+                    } else {
+                        markdown.push_str(line);
+                        markdown.push('\n');
+                    }
+                }
+                examples.push(Example {
+                    number: examples.len() + 1,
+                    section: section.clone(),
+                    markdown,
+                    html,
+                });
+            }
+        }
+        examples
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    enum Construct {
+        Paragraph,
+        Heading,
+        CodeBlock,
+        Rule,
+        BlockQuote,
+        List,
+        ListItem,
+        Table,
+        Html,
+        Emphasis,
+        Code,
+        Link,
+        Image,
+    }
+
+    type Counts = BTreeMap<Construct, usize>;
+
+    fn count(counts: &mut Counts, construct: Construct) {
+        *counts.entry(construct).or_insert(0) += 1;
+    }
+
+    /// Constructs in the expected html, by opening tag.
+    /// Directly nested emphasis is one emphasis of combined strength.
+    /// Raw html only needs to be kept once per example, in whatever form.
+    fn html_constructs(html: &str) -> Counts {
+        let mut counts = Counts::new();
+        let mut in_pre = false;
+        let mut in_emphasis = false;
+        for tag in html.split('<').skip(1) {
+            let nested_emphasis = mem::replace(&mut in_emphasis, false);
+            let closing = tag.starts_with('/');
+            let name: String = tag.trim_start_matches('/')
+                .chars()
+                .take_while(char::is_ascii_alphanumeric)
+                .collect::<String>()
+                .to_lowercase();
+            if name == "pre" {
+                in_pre = !closing;
+            }
+            if closing || name.is_empty() {
+                continue;
+            }
+            let construct = match name.as_str() {
+                "p" => Construct::Paragraph,
+                "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => Construct::Heading,
+                "pre" => Construct::CodeBlock,
+                "hr" => Construct::Rule,
+                "blockquote" => Construct::BlockQuote,
+                "ul" | "ol" => Construct::List,
+                "li" => Construct::ListItem,
+                "table" => Construct::Table,
+                "em" | "strong" | "del" => {
+                    in_emphasis = tag.ends_with('>');
+                    if nested_emphasis {
+                        continue;
+                    }
+                    Construct::Emphasis
+                },
+                "code" if in_pre => continue,
+                "code" => Construct::Code,
+                "a" => Construct::Link,
+                "img" => Construct::Image,
+                // parts of their parent construct, or line breaks that are just text
+                "br" | "input" | "thead" | "tbody" | "tr" | "th" | "td" => continue,
+                _ => {
+                    counts.insert(Construct::Html, 1);
+                    continue;
+                },
+            };
+            count(&mut counts, construct);
+        }
+        counts
+    }
+
+    fn em_or_text_constructs(items: &[EmOrText], counts: &mut Counts) {
+        for item in items {
+            if let EmOrText::Em(em) = item {
+                count(counts, Construct::Emphasis);
+                if em.tags.contains(&ConvertOptions::default().code_tag) {
+                    count(counts, Construct::Code);
+                }
+            }
+        }
+    }
+
+    fn paragraph_constructs(par: &Paragraph, block: bool, counts: &mut Counts) {
+        let opts = ConvertOptions::default();
+        let mut inline = false;
+        for item in &par.items {
+            match item {
+                ParagraphItem::Text(text) => inline |= !text.trim().is_empty(),
+                ParagraphItem::MText(mtext) => {
+                    inline = true;
+                    if mtext.tags.contains(&opts.code_tag) {
+                        count(counts, Construct::Code);
+                    } else if !mtext.tags.contains(&opts.math_tag) {
+                        count(counts, Construct::Emphasis);
+                    }
+                },
+                ParagraphItem::Em(em) if em.etype == EmType::Deemphasis
+                    && em.strength == EmStrength::Light && em.text == "html(" =>
+                {
+                    counts.insert(Construct::Html, 1);
+                },
+                ParagraphItem::Em(em) if em.etype == EmType::Deemphasis
+                    && em.strength == EmStrength::Light && em.text == ")" => { },
+                ParagraphItem::Em(_) => {
+                    inline = true;
+                    count(counts, Construct::Emphasis);
+                },
+                ParagraphItem::Code(Ok(code)) if code.tags.contains(&opts.unconverted_tag) => {
+                    counts.insert(Construct::Html, 1);
+                },
+                ParagraphItem::Code(Ok(_)) => count(counts, Construct::CodeBlock),
+                ParagraphItem::Code(Err(_)) => { },
+                ParagraphItem::Link(link) if link.tags.contains("footnote-ref") => { },
+                ParagraphItem::Link(link) => {
+                    inline = true;
+                    if link.tags.contains("image") {
+                        count(counts, Construct::Image);
+                    } else {
+                        count(counts, Construct::Link);
+                    }
+                    em_or_text_constructs(&link.items, counts);
+                },
+                ParagraphItem::List(list) => {
+                    count(counts, Construct::List);
+                    for item in &list.items {
+                        count(counts, Construct::ListItem);
+                        paragraph_constructs(item, true, counts);
+                    }
+                },
+                ParagraphItem::Table(table) => {
+                    count(counts, Construct::Table);
+                    for row in &table.rows {
+                        for cell in &row.items {
+                            paragraph_constructs(cell, false, counts);
+                        }
+                    }
+                },
+            }
+        }
+        if block && inline {
+            count(counts, Construct::Paragraph);
+        }
+    }
+
+    fn section_constructs(section: &Section, counts: &mut Counts) {
+        if section.tags.contains("blockquote") {
+            count(counts, Construct::BlockQuote);
+        } else if !section.tags.contains("footnote-def") {
+            count(counts, Construct::Heading);
+            em_or_text_constructs(&section.heading.items, counts);
+            for key in section.heading.props.keys() {
+                if key.strip_prefix("link-").is_some_and(|index| index.parse::<usize>().is_ok()) {
+                    count(counts, Construct::Link);
+                } else if key.starts_with("code-") {
+                    count(counts, Construct::Code);
+                }
+            }
+        }
+        for item in &section.items {
+            match item {
+                SectionItem::Paragraph(par) => paragraph_constructs(par, true, counts),
+                SectionItem::Section(section) => section_constructs(section, counts),
+            }
+        }
+    }
+
+    /// Constructs in the converted document.
+    fn doc_constructs(doc: &Doc) -> Counts {
+        let mut counts = Counts::new();
+        for item in &doc.items {
+            match item {
+                DocItem::Paragraph(par) => paragraph_constructs(par, true, &mut counts),
+                DocItem::Section(section) => section_constructs(section, &mut counts),
+            }
+        }
+        counts
+    }
+
+    /// Constructs of the expected html without a counterpart in the converted document.
+    fn missing(example: &Example, opts: &ConvertOptions) -> Vec<Construct> {
+        let (doc, _) = convert(&example.markdown, opts)
+            .unwrap_or_else(|e| panic!("example {}: {e:?}", example.number));
+        let found = doc_constructs(&doc);
+        html_constructs(&example.html).into_iter()
+            .filter(|(construct, n)| found.get(construct).copied().unwrap_or(0) < *n)
+            .map(|(construct, _)| construct)
+            .collect()
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum Mapped {
+        Fully,
+        Partially,
+        Not,
+    }
+
+    /// How well every section is mapped, in spec order, and the examples that are not fully
+    /// mapped. Printed as a report along the way.
+    fn report(
+        name: &str, spec: &str, opts: &ConvertOptions
+    ) -> (Vec<(String, Mapped)>, Vec<usize>) {
+        let examples = examples(spec);
+        let mut sections: Vec<(String, usize, usize, Vec<Construct>)> = Vec::new();
+        let mut lossy = Vec::new();
+        for example in &examples {
+            if sections.last().is_none_or(|(section, ..)| *section != example.section) {
+                sections.push((example.section.clone(), 0, 0, Vec::new()));
+            }
+            let Some((_, total, full, missed)) = sections.last_mut() else { unreachable!() };
+            let missing = missing(example, opts);
+            *total += 1;
+            if missing.is_empty() {
+                *full += 1;
+            } else {
+                lossy.push(example.number);
+            }
+            missed.extend(missing);
+            missed.sort();
+            missed.dedup();
+        }
+        let full: usize = sections.iter().map(|(_, _, full, _)| full).sum();
+        println!("{name}: {full}/{} examples fully mapped", examples.len());
+        let mut mapped = Vec::new();
+        for (section, total, full, missed) in sections {
+            let status = match full {
+                _ if full == total => Mapped::Fully,
+                0 => Mapped::Not,
+                _ => Mapped::Partially,
+            };
+            let label = format!("{status:?}");
+            println!("  {label:<9} {section} ({full}/{total}) {missed:?}");
+            mapped.push((section, status));
+        }
+        println!("  lossy examples: {lossy:?}");
+        (mapped, lossy)
+    }
+
+    /// Fails when the mapping of a section or example changes, better or worse.
+    fn check(
+        name: &str, spec: &str, opts: &ConvertOptions,
+        sections: &[(&str, Mapped)], lossy: &[usize],
+    ) {
+        let (mapped, lossy_found) = report(name, spec, opts);
+        let mapped: Vec<_> = mapped.iter()
+            .map(|(section, status)| (section.as_str(), *status))
+            .collect();
+        assert_eq!(mapped, sections);
+        assert_eq!(lossy_found, lossy);
+    }
+
+    #[test]
+    fn s_commonmark() {
+        use Mapped::*;
+        check(
+            "CommonMark",
+            include_str!("../spec/commonmark.txt"),
+            &ConvertOptions::default().extensions(Options::empty()),
+            &[
+                ("Tabs", Partially),
+                ("Backslash escapes", Partially),
+                ("Entity and numeric character references", Partially),
+                ("Precedence", Fully),
+                ("Thematic breaks", Partially),
+                ("ATX headings", Partially),
+                ("Setext headings", Partially),
+                ("Indented code blocks", Partially),
+                ("Fenced code blocks", Fully),
+                ("HTML blocks", Partially),
+                ("Link reference definitions", Partially),
+                ("Paragraphs", Fully),
+                ("Blank lines", Fully),
+                ("Block quotes", Partially),
+                ("List items", Partially),
+                ("Lists", Partially),
+                ("Inlines", Fully),
+                ("Code spans", Fully),
+                ("Emphasis and strong emphasis", Fully),
+                ("Links", Partially),
+                ("Images", Fully),
+                ("Autolinks", Fully),
+                ("Raw HTML", Partially),
+                ("Hard line breaks", Fully),
+                ("Soft line breaks", Fully),
+                ("Textual content", Fully),
+            ],
+            // thematic breaks, raw html, paragraphs merged in list items and empty containers
+            &[
+                4, 11, 21, 31, 43, 47, 50, 51, 52, 53, 54, 57, 58, 60, 61, 77, 85, 88, 92, 94, 96,
+                98, 99, 100, 101, 104, 105, 108, 115, 148, 149, 150, 159, 160, 162, 166, 167, 169,
+                190, 191, 218, 234, 237, 239, 240, 244, 246, 256, 258, 259, 262, 263, 277, 309,
+                316, 494, 613, 614, 615, 616, 623,
+            ],
+        );
+    }
+
+    #[test]
+    fn s_gfm() {
+        use Mapped::*;
+        check(
+            "GFM",
+            include_str!("../spec/gfm.txt"),
+            &ConvertOptions::default().extensions(
+                Options::ENABLE_TABLES | Options::ENABLE_TASKLISTS | Options::ENABLE_STRIKETHROUGH
+            ),
+            &[
+                ("Tables (extension)", Fully),
+                ("Task list items (extension)", Fully),
+                ("Strikethrough (extension)", Fully),
+                ("Autolinks (extension)", Not),
+                ("Disallowed Raw HTML (extension)", Not),
+            ],
+            // the parser has neither extended autolinks nor the tag filter
+            &[15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26],
+        );
+    }
+}
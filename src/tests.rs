@@ -1,4 +1,5 @@
 #[cfg(test)]
+#[allow(clippy::module_inception, clippy::needless_update)]
 mod tests {
     use crate::*;
 
@@ -26,6 +27,16 @@ mod tests {
         }
     }
 
+    macro_rules! test_with {
+        ($name:ident, $opts:expr, $string:expr, $result:expr) => {
+            #[test]
+            fn $name() {
+                let incodoc = parse_md_to_incodoc_with($string, &$opts);
+                assert_eq!(incodoc, $result);
+            }
+        }
+    }
+
     test!(
         t_empty,
         "",
@@ -1151,6 +1162,171 @@ let x = 0;
         }
     );
 
+    test!(
+        t_codeblock_c2,
+        "
+par
+
+    code {
+        code
+    }
+        ",
+        Doc {
+            items: vec![
+                DocItem::Paragraph(Paragraph {
+                    items: vec![
+                        ParagraphItem::Text("par".to_string()),
+                    ],
+                    ..Default::default()
+                }),
+                DocItem::Paragraph(Paragraph {
+                    items: vec![
+                        ParagraphItem::Code(Ok(CodeBlock{
+                            code:
+"code {
+    code
+}
+"
+                                .to_string(),
+                            tags: hset!(["indented"]),
+                            ..Default::default()
+                        })),
+                    ],
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        }
+    );
+
+    test!(
+        t_codeblock_c3,
+        "
+# h
+
+    a
+
+    b
+post
+        ",
+        Doc {
+            items: vec![
+                DocItem::Section(Section {
+                    heading: Heading {
+                        level: 0,
+                        items: vec![
+                            EmOrText::Text("h".to_string()),
+                        ],
+                        ..Default::default()
+                    },
+                    items: vec![
+                        SectionItem::Paragraph(Paragraph {
+                            items: vec![
+                                ParagraphItem::Code(Ok(CodeBlock{
+                                    code: "a\n\nb\n".to_string(),
+                                    tags: hset!(["indented"]),
+                                    ..Default::default()
+                                })),
+                                ParagraphItem::Text("post".to_string()),
+                            ],
+                            ..Default::default()
+                        }),
+                    ],
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        }
+    );
+
+    test!(
+        t_codeblock_c4,
+        "
+```rust,ignore no_run title=\"main.rs\" {1,3-5}
+let x = 0;
+```
+        ",
+        Doc {
+            items: vec![
+                DocItem::Paragraph(Paragraph {
+                    items: vec![
+                        ParagraphItem::Code(Ok(CodeBlock{
+                            language: "rust".to_string(),
+                            code: "let x = 0;\n".to_string(),
+                            tags: hset!(["ignore", "no_run"]),
+                            props: props!([
+                                ("title".to_string(), PropVal::String("main.rs".to_string())),
+                                ("highlight".to_string(), PropVal::String("1,3-5".to_string())),
+                            ]),
+                            ..Default::default()
+                        })),
+                    ],
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        }
+    );
+
+    test!(
+        t_codeblock_c5,
+        "
+```{.haskell .numberLines #fib startFrom=10 filename='fib hs'}
+fib = 0
+```
+        ",
+        Doc {
+            items: vec![
+                DocItem::Paragraph(Paragraph {
+                    items: vec![
+                        ParagraphItem::Code(Ok(CodeBlock{
+                            language: "haskell".to_string(),
+                            code: "fib = 0\n".to_string(),
+                            tags: hset!(["numberLines"]),
+                            props: props!([
+                                ("id".to_string(), PropVal::String("fib".to_string())),
+                                ("startFrom".to_string(), PropVal::Int(10)),
+                                ("filename".to_string(), PropVal::String("fib hs".to_string())),
+                            ]),
+                            ..Default::default()
+                        })),
+                    ],
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        }
+    );
+
+    test!(
+        t_codeblock_c6,
+        "
+```rust title=it's here {.c key='a b'}
+let x = 0;
+```
+        ",
+        Doc {
+            items: vec![
+                DocItem::Paragraph(Paragraph {
+                    items: vec![
+                        ParagraphItem::Code(Ok(CodeBlock{
+                            language: "rust".to_string(),
+                            code: "let x = 0;\n".to_string(),
+                            tags: hset!(["here", "c"]),
+                            props: props!([
+                                ("title".to_string(), PropVal::String("it's".to_string())),
+                                ("key".to_string(), PropVal::String("a b".to_string())),
+                            ]),
+                            ..Default::default()
+                        })),
+                    ],
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        }
+    );
+
     test!(
         t_list_c0,
         "
@@ -1643,81 +1819,245 @@ let x = 0;
         }
     );
 
+    // previous bug ended the outer item with the nested list, moving the rest out of the list
     test!(
-        t_emphasis_c0,
+        t_list_c11,
         "
-*a*
-        ",
-        Doc {
-            items: vec![DocItem::Paragraph(Paragraph {
-                items: vec![
-                    ParagraphItem::Em(Emphasis {
-                        strength: EmStrength::Light,
-                        text: "a".to_string(),
-                        ..Default::default()
-                    }),
-                ],
-                ..Default::default()
-            })],
-            ..Default::default()
-        }
-    );
+1. aaa
+   - bbb
 
-    test!(
-        t_emphasis_c1,
-        "
-**a**
+   ccc
         ",
         Doc {
-            items: vec![DocItem::Paragraph(Paragraph {
-                items: vec![
-                    ParagraphItem::Em(Emphasis {
-                        strength: EmStrength::Medium,
-                        text: "a".to_string(),
-                        ..Default::default()
-                    }),
-                ],
-                ..Default::default()
-            })],
+            items: vec![
+                DocItem::Paragraph(Paragraph {
+                    items: vec![
+                        ParagraphItem::List(List{
+                            ltype: ListType::Distinct,
+                            items: vec![
+                                Paragraph {
+                                    items: vec![
+                                        ParagraphItem::Text("aaa".to_string()),
+                                        ParagraphItem::List(List {
+                                            ltype: ListType::Identical,
+                                            items: vec![
+                                                Paragraph {
+                                                    items: vec![
+                                                        ParagraphItem::Text("bbb".to_string()),
+                                                    ],
+                                                    ..Default::default()
+                                                },
+                                            ],
+                                            ..Default::default()
+                                        }),
+                                        ParagraphItem::Text("\n\n".to_string()),
+                                        ParagraphItem::Text("ccc".to_string()),
+                                    ],
+                                    ..Default::default()
+                                },
+                            ],
+                            ..Default::default()
+                        }),
+                    ],
+                    ..Default::default()
+                }),
+            ],
             ..Default::default()
         }
     );
 
+    // a heading in a list item starts a section, what is before it in the list stays before it
     test!(
-        t_emphasis_c2,
+        t_list_c12,
         "
-***a***
+| aaa |
+|-----|
+
+- # bbb
         ",
         Doc {
-            items: vec![DocItem::Paragraph(Paragraph {
-                items: vec![
-                    ParagraphItem::Em(Emphasis {
-                        strength: EmStrength::Strong,
-                        text: "a".to_string(),
+            items: vec![
+                DocItem::Paragraph(Paragraph {
+                    items: vec![
+                        ParagraphItem::Table(Table {
+                            rows: vec![
+                                TableRow {
+                                    items: vec![
+                                        Paragraph {
+                                            items: vec![
+                                                ParagraphItem::Text("aaa".to_string()),
+                                            ],
+                                            ..Default::default()
+                                        },
+                                    ],
+                                    is_header: true,
+                                    ..Default::default()
+                                },
+                            ],
+                            ..Default::default()
+                        }),
+                    ],
+                    ..Default::default()
+                }),
+                DocItem::Section(Section {
+                    heading: Heading {
+                        level: 0,
+                        items: vec![EmOrText::Text("bbb".to_string())],
                         ..Default::default()
-                    }),
-                ],
-                ..Default::default()
-            })],
+                    },
+                    items: vec![
+                        SectionItem::Paragraph(Paragraph {
+                            items: vec![
+                                ParagraphItem::List(List{
+                                    ltype: ListType::Identical,
+                                    items: vec![Paragraph::default()],
+                                    ..Default::default()
+                                }),
+                            ],
+                            ..Default::default()
+                        }),
+                    ],
+                    ..Default::default()
+                }),
+            ],
             ..Default::default()
         }
     );
 
     test!(
-        t_emphasis_c3,
+        t_list_c13,
         "
-~~a~~
+- aaa
+- > bbb
+- ccc
         ",
         Doc {
-            items: vec![DocItem::Paragraph(Paragraph {
-                items: vec![
-                    ParagraphItem::Em(Emphasis {
-                        strength: EmStrength::Medium,
-                        etype: EmType::Deemphasis,
-                        text: "a".to_string(),
-                        ..Default::default()
-                    }),
-                ],
+            items: vec![
+                DocItem::Paragraph(Paragraph {
+                    items: vec![
+                        ParagraphItem::List(List{
+                            ltype: ListType::Identical,
+                            items: vec![
+                                Paragraph {
+                                    items: vec![ParagraphItem::Text("aaa".to_string())],
+                                    ..Default::default()
+                                },
+                            ],
+                            ..Default::default()
+                        }),
+                    ],
+                    ..Default::default()
+                }),
+                DocItem::Section(Section {
+                    heading: Heading {
+                        level: 100,
+                        ..Default::default()
+                    },
+                    items: vec![
+                        SectionItem::Paragraph(Paragraph {
+                            items: vec![ParagraphItem::Text("bbb".to_string())],
+                            ..Default::default()
+                        }),
+                    ],
+                    tags: hset!(["blockquote"]),
+                    ..Default::default()
+                }),
+                DocItem::Paragraph(Paragraph {
+                    items: vec![
+                        ParagraphItem::List(List{
+                            ltype: ListType::Identical,
+                            items: vec![
+                                Paragraph::default(),
+                                Paragraph {
+                                    items: vec![ParagraphItem::Text("ccc".to_string())],
+                                    ..Default::default()
+                                },
+                            ],
+                            ..Default::default()
+                        }),
+                    ],
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        }
+    );
+
+    test!(
+        t_emphasis_c0,
+        "
+*a*
+        ",
+        Doc {
+            items: vec![DocItem::Paragraph(Paragraph {
+                items: vec![
+                    ParagraphItem::Em(Emphasis {
+                        strength: EmStrength::Light,
+                        text: "a".to_string(),
+                        ..Default::default()
+                    }),
+                ],
+                ..Default::default()
+            })],
+            ..Default::default()
+        }
+    );
+
+    test!(
+        t_emphasis_c1,
+        "
+**a**
+        ",
+        Doc {
+            items: vec![DocItem::Paragraph(Paragraph {
+                items: vec![
+                    ParagraphItem::Em(Emphasis {
+                        strength: EmStrength::Medium,
+                        text: "a".to_string(),
+                        ..Default::default()
+                    }),
+                ],
+                ..Default::default()
+            })],
+            ..Default::default()
+        }
+    );
+
+    test!(
+        t_emphasis_c2,
+        "
+***a***
+        ",
+        Doc {
+            items: vec![DocItem::Paragraph(Paragraph {
+                items: vec![
+                    ParagraphItem::Em(Emphasis {
+                        strength: EmStrength::Strong,
+                        text: "a".to_string(),
+                        ..Default::default()
+                    }),
+                ],
+                ..Default::default()
+            })],
+            ..Default::default()
+        }
+    );
+
+    test!(
+        t_emphasis_c3,
+        "
+~~a~~
+        ",
+        Doc {
+            items: vec![DocItem::Paragraph(Paragraph {
+                items: vec![
+                    ParagraphItem::Em(Emphasis {
+                        strength: EmStrength::Medium,
+                        etype: EmType::Deemphasis,
+                        text: "a".to_string(),
+                        ..Default::default()
+                    }),
+                ],
                 ..Default::default()
             })],
             ..Default::default()
@@ -2435,6 +2775,7 @@ pre [[url]] post
                             EmOrText::Text("url".to_string()),
                         ],
                         url: "url".to_string(),
+                        tags: hset!(["wikilink"]),
                         ..Default::default()
                     }),
                     ParagraphItem::Text(" post".to_string()),
@@ -2459,6 +2800,7 @@ pre [[url|link]] post
                             EmOrText::Text("link".to_string()),
                         ],
                         url: "url".to_string(),
+                        tags: hset!(["wikilink"]),
                         ..Default::default()
                     }),
                     ParagraphItem::Text(" post".to_string()),
@@ -2578,7 +2920,10 @@ pre ![image *em*](url 'title') post
                         ],
                         url: "url".to_string(),
                         tags: hset!(["image"]),
-                        props: props!([("title".to_string(), PropVal::String("title".to_string()))]),
+                        props: props!([
+                            ("title".to_string(), PropVal::String("title".to_string())),
+                            ("alt".to_string(), PropVal::String("image em".to_string())),
+                        ]),
                     }),
                     ParagraphItem::Text(" post".to_string()),
                 ],
@@ -2602,8 +2947,10 @@ pre ![[url]] post
                             EmOrText::Text("url".to_string()),
                         ],
                         url: "url".to_string(),
-                        tags: hset!(["image"]),
-                        ..Default::default()
+                        tags: hset!(["image", "wikilink"]),
+                        props: props!([
+                            ("alt".to_string(), PropVal::String("url".to_string())),
+                        ]),
                     }),
                     ParagraphItem::Text(" post".to_string()),
                 ],
@@ -2627,8 +2974,10 @@ pre ![[url|image]] post
                             EmOrText::Text("image".to_string()),
                         ],
                         url: "url".to_string(),
-                        tags: hset!(["image"]),
-                        ..Default::default()
+                        tags: hset!(["image", "wikilink"]),
+                        props: props!([
+                            ("alt".to_string(), PropVal::String("image".to_string())),
+                        ]),
                     }),
                     ParagraphItem::Text(" post".to_string()),
                 ],
@@ -2658,6 +3007,7 @@ pre ![image][ref] post
                         props: props!([
                             ("title".to_string(), PropVal::String("title".to_string())),
                             ("link-ref".to_string(), PropVal::String("ref".to_string())),
+                            ("alt".to_string(), PropVal::String("image".to_string())),
                         ]),
                         ..Default::default()
                     }),
@@ -2689,6 +3039,7 @@ pre ![ref][] post
                         props: props!([
                             ("title".to_string(), PropVal::String("title".to_string())),
                             ("link-ref".to_string(), PropVal::String("ref".to_string())),
+                            ("alt".to_string(), PropVal::String("ref".to_string())),
                         ]),
                         ..Default::default()
                     }),
@@ -2720,6 +3071,7 @@ pre ![ref] post
                         props: props!([
                             ("title".to_string(), PropVal::String("title".to_string())),
                             ("link-ref".to_string(), PropVal::String("ref".to_string())),
+                            ("alt".to_string(), PropVal::String("ref".to_string())),
                         ]),
                         ..Default::default()
                     }),
@@ -2867,6 +3219,59 @@ post
         }
     );
 
+    test!(
+        t_math_c3,
+        "*a $x$ b*",
+        Doc {
+            items: vec![DocItem::Paragraph(Paragraph {
+                items: vec![
+                    ParagraphItem::Em(Emphasis {
+                        text: "a ".to_string(),
+                        ..Default::default()
+                    }),
+                    ParagraphItem::MText(TextWithMeta {
+                        text: "x".to_string(),
+                        tags: hset!(["latex-math"]),
+                        ..Default::default()
+                    }),
+                    ParagraphItem::Em(Emphasis {
+                        text: " b".to_string(),
+                        ..Default::default()
+                    }),
+                ],
+                ..Default::default()
+            })],
+            ..Default::default()
+        }
+    );
+
+    test!(
+        t_math_c4,
+        "*a $$x$$ b*",
+        Doc {
+            items: vec![DocItem::Paragraph(Paragraph {
+                items: vec![
+                    ParagraphItem::Em(Emphasis {
+                        text: "a ".to_string(),
+                        ..Default::default()
+                    }),
+                    ParagraphItem::Code(Ok(CodeBlock {
+                        language: "latex-math".to_string(),
+                        code: "x".to_string(),
+                        mode: CodeModeHint::Replace,
+                        ..Default::default()
+                    })),
+                    ParagraphItem::Em(Emphasis {
+                        text: " b".to_string(),
+                        ..Default::default()
+                    }),
+                ],
+                ..Default::default()
+            })],
+            ..Default::default()
+        }
+    );
+
     test!(
         t_footnote_c0,
         "
@@ -2881,7 +3286,7 @@ footnote [^0]
                             EmOrText::Text("0".to_string()),
                         ],
                         url: "#footnote-0".to_string(),
-                        tags: hset!(["footnote-ref"]),
+                        tags: hset!(["footnote-ref", "dangling-footnote-ref"]),
                         ..Default::default()
                     }),
                 ],
@@ -2909,7 +3314,7 @@ footnote [^0]
                     items: vec![ParagraphItem::Text("footnote def".to_string())],
                     ..Default::default()
                 })],
-                tags: hset!(["footnote-def"]),
+                tags: hset!(["footnote-def", "unreferenced-footnote"]),
                 props: props!([
                     ("id".to_string(), PropVal::String("footnote-0".to_string())),
                 ]),
@@ -3240,14 +3645,14 @@ prop p1 v1
         t_metadata_block_c5,
         "
 +++
-prop notanumber 0
+prop number 0
 +++
         ",
         Doc {
             props: props!([
                 (
-                    "notanumber".to_string(),
-                    PropVal::String("0".to_string())
+                    "number".to_string(),
+                    PropVal::Int(0)
                 ),
             ]),
             ..Default::default()
@@ -3259,7 +3664,7 @@ prop notanumber 0
         "
 +++
 tags a b c
-prop notanumber 0
+prop number 0
 this will be ignored
 nav
 end
@@ -3269,8 +3674,8 @@ end
             tags: hset!(["a", "b", "c"]),
             props: props!([
                 (
-                    "notanumber".to_string(),
-                    PropVal::String("0".to_string())
+                    "number".to_string(),
+                    PropVal::Int(0)
                 ),
             ]),
             navs: vec![
@@ -3285,7 +3690,7 @@ end
         "
 +++
 tags a b c
-prop notanumber 0
+prop number 0
 this will be ignored
 nav
   link link in $ top level
@@ -3297,8 +3702,8 @@ end
             tags: hset!(["a", "b", "c"]),
             props: props!([
                 (
-                    "notanumber".to_string(),
-                    PropVal::String("0".to_string())
+                    "number".to_string(),
+                    PropVal::Int(0)
                 ),
             ]),
             navs: vec![
@@ -4785,5 +5190,2000 @@ a
             ..Default::default()
         }
     );
-}
 
+    test_with!(
+        t_options_c0,
+        ConvertOptions::default().code_tag("inline-code").math_tag("tex"),
+        "
+`code` $math$
+        ",
+        Doc {
+            items: vec![DocItem::Paragraph(Paragraph {
+                items: vec![
+                    ParagraphItem::MText(TextWithMeta{
+                        text: "code".to_string(),
+                        tags: hset!(["inline-code"]),
+                        ..Default::default()
+                    }),
+                    ParagraphItem::Text(" ".to_string()),
+                    ParagraphItem::MText(TextWithMeta{
+                        text: "math".to_string(),
+                        tags: hset!(["tex"]),
+                        ..Default::default()
+                    }),
+                ],
+                ..Default::default()
+            })],
+            ..Default::default()
+        }
+    );
+
+    test_with!(
+        t_options_c1,
+        ConvertOptions::default().extension(Options::ENABLE_STRIKETHROUGH, false),
+        "
+~~a~~
+        ",
+        Doc {
+            items: vec![DocItem::Paragraph(Paragraph {
+                items: vec![
+                    ParagraphItem::Text("~~a~~".to_string()),
+                ],
+                ..Default::default()
+            })],
+            ..Default::default()
+        }
+    );
+
+    test_with!(
+        t_options_c2,
+        ConvertOptions::default()
+            .html_blocks(HtmlBlockPolicy::Drop)
+            .inline_html(InlineHtmlPolicy::Strip),
+        "
+pre <b>a</b> post
+
+<p> inside html</p>
+        ",
+        Doc {
+            items: vec![DocItem::Paragraph(Paragraph {
+                items: vec![
+                    ParagraphItem::Text("pre ".to_string()),
+                    ParagraphItem::Text("a".to_string()),
+                    ParagraphItem::Text(" post".to_string()),
+                ],
+                ..Default::default()
+            })],
+            ..Default::default()
+        }
+    );
+
+    test_with!(
+        t_options_c3,
+        ConvertOptions::default().micro_section_heading_level(10),
+        "
+> quote
+        ",
+        Doc {
+            items: vec![
+                DocItem::Section(Section {
+                    heading: Heading {
+                        level: 10,
+                        ..Default::default()
+                    },
+                    items: vec![
+                        SectionItem::Paragraph(Paragraph {
+                            items: vec![
+                                ParagraphItem::Text("quote".to_string()),
+                            ],
+                            ..Default::default()
+                        }),
+                    ],
+                    tags: hset!(["blockquote"]),
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        }
+    );
+
+    macro_rules! test_diagnostics {
+        ($name:ident, $string:expr, $result:expr) => {
+            #[test]
+            fn $name() {
+                let (_, diagnostics) = convert($string, &ConvertOptions::default()).unwrap();
+                assert_eq!(diagnostics, $result);
+            }
+        }
+    }
+
+    test_diagnostics!(
+        t_diagnostics_c0,
+        "par",
+        vec![]
+    );
+
+    test_diagnostics!(
+        t_diagnostics_c1,
+        "---\ntitle: x\nnot yaml\n---\n",
+        vec![Diagnostic {
+            kind: DiagnosticKind::MetadataLine("not yaml".to_string()),
+            range: 13..21,
+        }]
+    );
+
+    test_diagnostics!(
+        t_diagnostics_c2,
+        "+++\ntags a\nwhat is this\n  prop p v\n+++\n",
+        vec![Diagnostic {
+            kind: DiagnosticKind::MetadataLine("what is this".to_string()),
+            range: 11..23,
+        }]
+    );
+
+    test_diagnostics!(
+        t_diagnostics_c3,
+        "| a | b |\n|--:|---|\n| 1 | 2 |\n",
+        vec![]
+    );
+
+    test_diagnostics!(
+        t_diagnostics_c4,
+        "term\n: definition\n",
+        vec![]
+    );
+    test_diagnostics!(
+        t_diagnostics_c5,
+        "a[^x] b[^Y]\n\n[^y]: Y\n\n[^z]: Z\n",
+        vec![
+            Diagnostic {
+                kind: DiagnosticKind::DanglingFootnote("x".to_string()),
+                range: 1..5,
+            },
+            Diagnostic {
+                kind: DiagnosticKind::UnreferencedFootnote("z".to_string()),
+                range: 22..30,
+            },
+        ]
+    );
+
+    test_diagnostics!(
+        t_diagnostics_c6,
+        "a\n\n***\n\nb\n",
+        vec![Diagnostic {
+            kind: DiagnosticKind::Unsupported("thematic break".to_string()),
+            range: 3..7,
+        }]
+    );
+
+    test_diagnostics!(
+        t_diagnostics_c7,
+        "[a ^s^ `c` *`e`*](u)",
+        vec![
+            Diagnostic {
+                kind: DiagnosticKind::Unsupported("super in link text".to_string()),
+                range: 4..5,
+            },
+            Diagnostic {
+                kind: DiagnosticKind::Unsupported("code in link text".to_string()),
+                range: 7..10,
+            },
+        ]
+    );
+
+    test_diagnostics!(
+        t_diagnostics_c8,
+        "---\n- a\n- k: v\n  # c\n  l: w\ntitle: x\n---\n",
+        vec![
+            Diagnostic {
+                kind: DiagnosticKind::MetadataLine("- a".to_string()),
+                range: 4..7,
+            },
+            Diagnostic {
+                kind: DiagnosticKind::MetadataLine("- k: v".to_string()),
+                range: 8..14,
+            },
+            Diagnostic {
+                kind: DiagnosticKind::MetadataLine("l: w".to_string()),
+                range: 23..27,
+            },
+        ]
+    );
+
+    test!(
+        t_link_code_c0,
+        "[a `c` *`e`*](u)",
+        Doc {
+            items: vec![DocItem::Paragraph(Paragraph {
+                items: vec![ParagraphItem::Link(Link {
+                    url: "u".to_string(),
+                    items: vec![
+                        EmOrText::Text("a ".to_string()),
+                        EmOrText::Text("c".to_string()),
+                        EmOrText::Text(" ".to_string()),
+                        EmOrText::Em(Emphasis {
+                            strength: EmStrength::Light,
+                            etype: EmType::Emphasis,
+                            text: "e".to_string(),
+                            tags: hset!(["code"]),
+                            ..Default::default()
+                        }),
+                    ],
+                    ..Default::default()
+                })],
+                ..Default::default()
+            })],
+            ..Default::default()
+        }
+    );
+
+    test_with!(
+        t_positions_c0,
+        ConvertOptions::default().source_positions(true).line_columns(true),
+        "# H\n\npar [l](u)\n",
+        Doc {
+            items: vec![
+                DocItem::Section(Section {
+                    heading: Heading {
+                        level: 0,
+                        items: vec![
+                            EmOrText::Text("H".to_string()),
+                        ],
+                        props: props!([
+                            ("src-start".to_string(), PropVal::Int(0)),
+                            ("src-end".to_string(), PropVal::Int(4)),
+                            ("src-line".to_string(), PropVal::Int(1)),
+                            ("src-column".to_string(), PropVal::Int(1)),
+                        ]),
+                        ..Default::default()
+                    },
+                    items: vec![
+                        SectionItem::Paragraph(Paragraph {
+                            items: vec![
+                                ParagraphItem::Text("par ".to_string()),
+                                ParagraphItem::Link(Link {
+                                    items: vec![
+                                        EmOrText::Text("l".to_string()),
+                                    ],
+                                    url: "u".to_string(),
+                                    props: props!([
+                                        ("src-start".to_string(), PropVal::Int(9)),
+                                        ("src-end".to_string(), PropVal::Int(15)),
+                                        ("src-line".to_string(), PropVal::Int(3)),
+                                        ("src-column".to_string(), PropVal::Int(5)),
+                                    ]),
+                                    ..Default::default()
+                                }),
+                            ],
+                            props: props!([
+                                ("src-start".to_string(), PropVal::Int(5)),
+                                ("src-end".to_string(), PropVal::Int(16)),
+                                ("src-line".to_string(), PropVal::Int(3)),
+                                ("src-column".to_string(), PropVal::Int(1)),
+                            ]),
+                            ..Default::default()
+                        }),
+                    ],
+                    props: props!([
+                        ("src-start".to_string(), PropVal::Int(0)),
+                        ("src-end".to_string(), PropVal::Int(16)),
+                        ("src-line".to_string(), PropVal::Int(1)),
+                        ("src-column".to_string(), PropVal::Int(1)),
+                    ]),
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        }
+    );
+
+    test_with!(
+        t_positions_c1,
+        ConvertOptions::default().source_positions(true),
+        "- x\n- y\n",
+        Doc {
+            items: vec![DocItem::Paragraph(Paragraph {
+                items: vec![
+                    ParagraphItem::List(List {
+                        ltype: ListType::Identical,
+                        items: vec![
+                            Paragraph {
+                                items: vec![
+                                    ParagraphItem::Text("x".to_string()),
+                                ],
+                                props: props!([
+                                    ("src-start".to_string(), PropVal::Int(0)),
+                                    ("src-end".to_string(), PropVal::Int(4)),
+                                ]),
+                                ..Default::default()
+                            },
+                            Paragraph {
+                                items: vec![
+                                    ParagraphItem::Text("y".to_string()),
+                                ],
+                                props: props!([
+                                    ("src-start".to_string(), PropVal::Int(4)),
+                                    ("src-end".to_string(), PropVal::Int(8)),
+                                ]),
+                                ..Default::default()
+                            },
+                        ],
+                        props: props!([
+                            ("src-start".to_string(), PropVal::Int(0)),
+                            ("src-end".to_string(), PropVal::Int(8)),
+                        ]),
+                        ..Default::default()
+                    }),
+                ],
+                props: props!([
+                    ("src-start".to_string(), PropVal::Int(0)),
+                    ("src-end".to_string(), PropVal::Int(8)),
+                ]),
+                ..Default::default()
+            })],
+            ..Default::default()
+        }
+    );
+
+    test!(
+        t_yaml_c0,
+        "
+---
+title: 2024
+date: 2024-03-05
+weight: 10
+draft: false
+quoted: '10'
+empty:
+---
+        ",
+        Doc {
+            props: props!([
+                ("title".to_string(), PropVal::String("2024".to_string())),
+                (
+                    "date".to_string(),
+                    PropVal::Date(Date { year: 2024, month: 3, day: 5 })
+                ),
+                ("weight".to_string(), PropVal::Int(10)),
+                ("draft".to_string(), PropVal::String("false".to_string())),
+                ("quoted".to_string(), PropVal::String("10".to_string())),
+            ]),
+            ..Default::default()
+        }
+    );
+
+    test!(
+        t_yaml_c1,
+        "
+---
+tags: [a, \"b c\"]
+keywords:
+  - d
+categories: e, f
+---
+        ",
+        Doc {
+            tags: hset!(["a", "b c", "d", "e", "f"]),
+            ..Default::default()
+        }
+    );
+
+    test!(
+        t_yaml_c2,
+        "
+---
+author:
+  name: someone
+  aliases: [x, y]
+summary: >
+  folded
+  text
+---
+        ",
+        Doc {
+            props: props!([
+                ("author.name".to_string(), PropVal::String("someone".to_string())),
+                ("author.aliases.0".to_string(), PropVal::String("x".to_string())),
+                ("author.aliases.1".to_string(), PropVal::String("y".to_string())),
+                ("summary".to_string(), PropVal::String("folded text".to_string())),
+            ]),
+            ..Default::default()
+        }
+    );
+
+    test!(
+        t_yaml_c3,
+        "
+---
+nav:
+  - Home: /index
+  - Guides:
+      Install: /install
+---
+        ",
+        Doc {
+            navs: vec![
+                Nav {
+                    links: vec![
+                        Link {
+                            url: "/index".to_string(),
+                            items: vec![EmOrText::Text("Home".to_string())],
+                            ..Default::default()
+                        },
+                    ],
+                    subs: vec![
+                        Nav {
+                            description: "Guides".to_string(),
+                            links: vec![
+                                Link {
+                                    url: "/install".to_string(),
+                                    items: vec![EmOrText::Text("Install".to_string())],
+                                    ..Default::default()
+                                },
+                            ],
+                            ..Default::default()
+                        },
+                    ],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        }
+    );
+
+    test!(
+        t_metadata_block_c13,
+        "
++++
+prop count -3
+prop ratio 0.5
+prop date 2024-03-05
+prop title \"a  quoted title\"
+prop words some more words
+prop quoted '12'
++++
+        ",
+        Doc {
+            props: props!([
+                ("count".to_string(), PropVal::Int(-3)),
+                ("ratio".to_string(), PropVal::String("0.5".to_string())),
+                (
+                    "date".to_string(),
+                    PropVal::Date(Date { year: 2024, month: 3, day: 5 })
+                ),
+                ("title".to_string(), PropVal::String("a  quoted title".to_string())),
+                ("words".to_string(), PropVal::String("some more words".to_string())),
+                ("quoted".to_string(), PropVal::String("12".to_string())),
+            ]),
+            ..Default::default()
+        }
+    );
+
+    test!(
+        t_heading_inline_c0,
+        "
+# a *b* **c** d
+        ",
+        Doc {
+            items: vec![
+                DocItem::Section(Section {
+                    heading: Heading {
+                        level: 0,
+                        items: vec![
+                            EmOrText::Text("a ".to_string()),
+                            EmOrText::Em(Emphasis {
+                                strength: EmStrength::Light,
+                                text: "b".to_string(),
+                                ..Default::default()
+                            }),
+                            EmOrText::Text(" ".to_string()),
+                            EmOrText::Em(Emphasis {
+                                strength: EmStrength::Medium,
+                                text: "c".to_string(),
+                                ..Default::default()
+                            }),
+                            EmOrText::Text(" d".to_string()),
+                        ],
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        }
+    );
+
+    test!(
+        t_heading_inline_c1,
+        "
+par
+# a `b` c [d](url) $m$
+        ",
+        Doc {
+            items: vec![
+                DocItem::Paragraph(Paragraph {
+                    items: vec![
+                        ParagraphItem::Text("par".to_string()),
+                    ],
+                    ..Default::default()
+                }),
+                DocItem::Section(Section {
+                    heading: Heading {
+                        level: 0,
+                        items: vec![
+                            EmOrText::Text("a ".to_string()),
+                            EmOrText::Text("b".to_string()),
+                            EmOrText::Text(" c ".to_string()),
+                            EmOrText::Text("d".to_string()),
+                            EmOrText::Text(" ".to_string()),
+                            EmOrText::Text("m".to_string()),
+                        ],
+                        props: props!([
+                            ("code-1".to_string(), PropVal::String("b".to_string())),
+                            ("link-3".to_string(), PropVal::String("url".to_string())),
+                            ("link-end-3".to_string(), PropVal::Int(4)),
+                            ("latex-math-5".to_string(), PropVal::String("m".to_string())),
+                        ]),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        }
+    );
+
+    test!(
+        t_heading_inline_c2,
+        "
+# it's *it's*
+        ",
+        Doc {
+            items: vec![
+                DocItem::Section(Section {
+                    heading: Heading {
+                        level: 0,
+                        items: vec![
+                            EmOrText::Text("it’s ".to_string()),
+                            EmOrText::Em(Emphasis {
+                                strength: EmStrength::Light,
+                                text: "it’s".to_string(),
+                                ..Default::default()
+                            }),
+                        ],
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        }
+    );
+
+    test!(
+        t_heading_inline_c3,
+        "
+# See [a *b*](u \"t\") end[^n]
+
+[^n]: note
+        ",
+        Doc {
+            items: vec![
+                DocItem::Section(Section {
+                    heading: Heading {
+                        level: 0,
+                        items: vec![
+                            EmOrText::Text("See ".to_string()),
+                            EmOrText::Text("a ".to_string()),
+                            EmOrText::Em(Emphasis {
+                                strength: EmStrength::Light,
+                                text: "b".to_string(),
+                                ..Default::default()
+                            }),
+                            EmOrText::Text(" end".to_string()),
+                            EmOrText::Text("n".to_string()),
+                        ],
+                        props: props!([
+                            ("link-1".to_string(), PropVal::String("u".to_string())),
+                            ("link-end-1".to_string(), PropVal::Int(3)),
+                            ("link-title-1".to_string(), PropVal::String("t".to_string())),
+                            ("link-4".to_string(), PropVal::String("#footnote-n".to_string())),
+                            ("link-end-4".to_string(), PropVal::Int(5)),
+                            ("link-tags-4".to_string(), PropVal::String("footnote-ref".to_string())),
+                        ]),
+                        ..Default::default()
+                    },
+                    items: vec![
+                        SectionItem::Section(Section {
+                            heading: Heading {
+                                level: 100,
+                                items: vec![EmOrText::Text("n".to_string())],
+                                ..Default::default()
+                            },
+                            items: vec![
+                                SectionItem::Paragraph(Paragraph {
+                                    items: vec![ParagraphItem::Text("note".to_string())],
+                                    ..Default::default()
+                                }),
+                            ],
+                            tags: hset!(["footnote-def"]),
+                            props: props!([
+                                ("id".to_string(), PropVal::String("footnote-n".to_string())),
+                            ]),
+                        }),
+                    ],
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        }
+    );
+
+    test!(
+        t_heading_inline_c4,
+        "# a $$x^2$$",
+        Doc {
+            items: vec![
+                DocItem::Section(Section {
+                    heading: Heading {
+                        level: 0,
+                        items: vec![
+                            EmOrText::Text("a ".to_string()),
+                            EmOrText::Text("x^2".to_string()),
+                        ],
+                        props: props!([
+                            ("latex-math-1".to_string(), PropVal::String("x^2".to_string())),
+                        ]),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        }
+    );
+
+    test_with!(
+        t_heading_ids_c0,
+        ConvertOptions::default().heading_ids(true),
+        "
+# Hello, *World*!
+# Hello World
+# Taken {#hello-world-2}
+        ",
+        Doc {
+            items: vec![
+                DocItem::Section(Section {
+                    heading: Heading {
+                        level: 0,
+                        items: vec![
+                            EmOrText::Text("Hello, ".to_string()),
+                            EmOrText::Em(Emphasis {
+                                strength: EmStrength::Light,
+                                text: "World".to_string(),
+                                ..Default::default()
+                            }),
+                            EmOrText::Text("!".to_string()),
+                        ],
+                        props: props!([
+                            ("id".to_string(), PropVal::String("hello-world".to_string())),
+                        ]),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }),
+                DocItem::Section(Section {
+                    heading: Heading {
+                        level: 0,
+                        items: vec![
+                            EmOrText::Text("Hello World".to_string()),
+                        ],
+                        props: props!([
+                            ("id".to_string(), PropVal::String("hello-world-1".to_string())),
+                        ]),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }),
+                DocItem::Section(Section {
+                    heading: Heading {
+                        level: 0,
+                        items: vec![
+                            EmOrText::Text("Taken".to_string()),
+                        ],
+                        props: props!([
+                            ("id".to_string(), PropVal::String("hello-world-2".to_string())),
+                        ]),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        }
+    );
+
+    test_with!(
+        t_heading_ids_c1,
+        ConvertOptions::default().heading_ids(true),
+        "
+[a](#a) [b](#b)
+# A
+        ",
+        Doc {
+            items: vec![
+                DocItem::Paragraph(Paragraph {
+                    items: vec![
+                        ParagraphItem::Link(Link {
+                            items: vec![
+                                EmOrText::Text("a".to_string()),
+                            ],
+                            url: "#a".to_string(),
+                            ..Default::default()
+                        }),
+                        ParagraphItem::Text(" ".to_string()),
+                        ParagraphItem::Link(Link {
+                            items: vec![
+                                EmOrText::Text("b".to_string()),
+                            ],
+                            url: "#b".to_string(),
+                            tags: hset!(["broken-anchor"]),
+                            ..Default::default()
+                        }),
+                    ],
+                    ..Default::default()
+                }),
+                DocItem::Section(Section {
+                    heading: Heading {
+                        level: 0,
+                        items: vec![
+                            EmOrText::Text("A".to_string()),
+                        ],
+                        props: props!([
+                            ("id".to_string(), PropVal::String("a".to_string())),
+                        ]),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        }
+    );
+
+    #[test]
+    fn t_heading_ids_diagnostics() {
+        let opts = ConvertOptions::default().heading_ids(true);
+        let (_, diagnostics) = convert("[a](#a) [b](#b)\n# A\n", &opts).unwrap();
+        assert_eq!(
+            diagnostics,
+            vec![Diagnostic { kind: DiagnosticKind::BrokenAnchor("#b".to_string()), range: 8..15 }]
+        );
+    }
+
+    fn em(strength: EmStrength, etype: EmType, text: &str, tags: &[&str]) -> ParagraphItem {
+        ParagraphItem::Em(Emphasis {
+            strength,
+            etype,
+            text: text.to_string(),
+            tags: hset!(tags),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn t_emphasis_matrix() {
+        use EmStrength::*;
+        use EmType::*;
+        let cases = [
+            ("**a ~~b~~ c**", vec![
+                em(Medium, Emphasis, "a ", &[]),
+                em(Medium, Emphasis, "b", &["strikethrough"]),
+                em(Medium, Emphasis, " c", &[]),
+            ]),
+            ("~~a **b** c~~", vec![
+                em(Medium, Deemphasis, "a ", &[]),
+                em(Medium, Emphasis, "b", &["strikethrough"]),
+                em(Medium, Deemphasis, " c", &[]),
+            ]),
+            ("*a ~~b~~*", vec![
+                em(Light, Emphasis, "a ", &[]),
+                em(Light, Emphasis, "b", &["strikethrough"]),
+            ]),
+            ("~~a *b*~~", vec![
+                em(Medium, Deemphasis, "a ", &[]),
+                em(Light, Emphasis, "b", &["strikethrough"]),
+            ]),
+            ("***a ~~b~~***", vec![
+                em(Strong, Emphasis, "a ", &[]),
+                em(Strong, Emphasis, "b", &["strikethrough"]),
+            ]),
+            ("****a****", vec![
+                em(Strong, Emphasis, "a", &[]),
+            ]),
+            ("**a ^b^ c**", vec![
+                em(Medium, Emphasis, "a ", &[]),
+                em(Medium, Emphasis, "b", &["super"]),
+                em(Medium, Emphasis, " c", &[]),
+            ]),
+            ("~~a ^b^~~", vec![
+                em(Medium, Deemphasis, "a ", &[]),
+                em(Medium, Deemphasis, "b", &["super"]),
+            ]),
+            ("^a *b*^", vec![
+                ParagraphItem::MText(TextWithMeta {
+                    text: "a ".to_string(),
+                    tags: hset!(["super"]),
+                    ..Default::default()
+                }),
+                em(Light, Emphasis, "b", &["super"]),
+            ]),
+            ("~a **b**~", vec![
+                ParagraphItem::MText(TextWithMeta {
+                    text: "a ".to_string(),
+                    tags: hset!(["sub"]),
+                    ..Default::default()
+                }),
+                em(Medium, Emphasis, "b", &["sub"]),
+            ]),
+            ("*a **b ~~c~~** d*", vec![
+                em(Light, Emphasis, "a ", &[]),
+                em(Strong, Emphasis, "b ", &[]),
+                em(Strong, Emphasis, "c", &["strikethrough"]),
+                em(Light, Emphasis, " d", &[]),
+            ]),
+            ("**a** ~~b~~ *c*", vec![
+                em(Medium, Emphasis, "a", &[]),
+                ParagraphItem::Text(" ".to_string()),
+                em(Medium, Deemphasis, "b", &[]),
+                ParagraphItem::Text(" ".to_string()),
+                em(Light, Emphasis, "c", &[]),
+            ]),
+        ];
+        for (md, mut items) in cases {
+            items.insert(0, ParagraphItem::Text("pre ".to_string()));
+            items.push(ParagraphItem::Text(" post".to_string()));
+            let doc = parse_md_to_incodoc(&format!("pre {md} post"));
+            let expected = Doc {
+                items: vec![DocItem::Paragraph(Paragraph { items, ..Default::default() })],
+                ..Default::default()
+            };
+            assert_eq!(doc, expected, "{md}");
+        }
+    }
+
+    test!(
+        t_table_align_c0,
+        "
+A | B | C | D
+:-|:-:|--:|--
+1 | 2 | 3 | 4
+        ",
+        Doc {
+            items: vec![
+                DocItem::Paragraph(Paragraph {
+                    items: vec![ParagraphItem::Table(Table {
+                        rows: vec![
+                            TableRow {
+                                is_header: true,
+                                items: vec![
+                                    Paragraph {
+                                        items: vec![
+                                            ParagraphItem::Text("A".to_string()),
+                                        ],
+                                        props: props!([
+                                            ("align".to_string(), PropVal::String("left".to_string())),
+                                        ]),
+                                        ..Default::default()
+                                    },
+                                    Paragraph {
+                                        items: vec![
+                                            ParagraphItem::Text("B".to_string()),
+                                        ],
+                                        props: props!([
+                                            (
+                                                "align".to_string(),
+                                                PropVal::String("center".to_string())
+                                            ),
+                                        ]),
+                                        ..Default::default()
+                                    },
+                                    Paragraph {
+                                        items: vec![
+                                            ParagraphItem::Text("C".to_string()),
+                                        ],
+                                        props: props!([
+                                            (
+                                                "align".to_string(),
+                                                PropVal::String("right".to_string())
+                                            ),
+                                        ]),
+                                        ..Default::default()
+                                    },
+                                    Paragraph {
+                                        items: vec![
+                                            ParagraphItem::Text("D".to_string()),
+                                        ],
+                                        ..Default::default()
+                                    },
+                                ],
+                                ..Default::default()
+                            },
+                            TableRow {
+                                items: vec![
+                                    Paragraph {
+                                        items: vec![
+                                            ParagraphItem::Text("1".to_string()),
+                                        ],
+                                        ..Default::default()
+                                    },
+                                    Paragraph {
+                                        items: vec![
+                                            ParagraphItem::Text("2".to_string()),
+                                        ],
+                                        ..Default::default()
+                                    },
+                                    Paragraph {
+                                        items: vec![
+                                            ParagraphItem::Text("3".to_string()),
+                                        ],
+                                        ..Default::default()
+                                    },
+                                    Paragraph {
+                                        items: vec![
+                                            ParagraphItem::Text("4".to_string()),
+                                        ],
+                                        ..Default::default()
+                                    },
+                                ],
+                                ..Default::default()
+                            },
+                        ],
+                        props: props!([
+                            (
+                                "align".to_string(),
+                                PropVal::String("left,center,right,none".to_string())
+                            ),
+                        ]),
+                        ..Default::default()
+                    })],
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        }
+    );
+
+    test!(
+        t_deflist_c0,
+        "
+Apple
+: A fruit.
+: A company.
+
+Orange
+: Citrus.
+
+  More text.
+        ",
+        Doc {
+            items: vec![
+                DocItem::Paragraph(Paragraph {
+                    items: vec![
+                        ParagraphItem::List(List {
+                            ltype: ListType::Identical,
+                            items: vec![
+                                Paragraph {
+                                    items: vec![ParagraphItem::Text("Apple".to_string())],
+                                    tags: hset!(["deflist-term"]),
+                                    ..Default::default()
+                                },
+                                Paragraph {
+                                    items: vec![ParagraphItem::Text("A fruit.".to_string())],
+                                    tags: hset!(["deflist-def"]),
+                                    ..Default::default()
+                                },
+                                Paragraph {
+                                    items: vec![ParagraphItem::Text("A company.".to_string())],
+                                    tags: hset!(["deflist-def"]),
+                                    ..Default::default()
+                                },
+                                Paragraph {
+                                    items: vec![ParagraphItem::Text("Orange".to_string())],
+                                    tags: hset!(["deflist-term"]),
+                                    ..Default::default()
+                                },
+                                Paragraph {
+                                    items: vec![
+                                        ParagraphItem::Text("Citrus.".to_string()),
+                                        ParagraphItem::Text("\n\n".to_string()),
+                                        ParagraphItem::Text("More text.".to_string()),
+                                    ],
+                                    tags: hset!(["deflist-def"]),
+                                    ..Default::default()
+                                },
+                            ],
+                            tags: hset!(["deflist"]),
+                            ..Default::default()
+                        }),
+                    ],
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        }
+    );
+
+    test_with!(
+        t_wikilink_c0,
+        ConvertOptions::default().wikilink_resolver(|page| {
+            (page == "Page").then(|| format!("notes/{}.md", page.to_lowercase()))
+        }),
+        "[[Page#Intro|intro]] [[Missing]] [[#Top]]",
+        Doc {
+            items: vec![DocItem::Paragraph(Paragraph {
+                items: vec![
+                    ParagraphItem::Link(Link {
+                        items: vec![EmOrText::Text("intro".to_string())],
+                        url: "notes/page.md#Intro".to_string(),
+                        tags: hset!(["wikilink"]),
+                        ..Default::default()
+                    }),
+                    ParagraphItem::Text(" ".to_string()),
+                    ParagraphItem::Link(Link {
+                        items: vec![EmOrText::Text("Missing".to_string())],
+                        url: "Missing".to_string(),
+                        tags: hset!(["wikilink", "unresolved-wikilink"]),
+                        ..Default::default()
+                    }),
+                    ParagraphItem::Text(" ".to_string()),
+                    ParagraphItem::Link(Link {
+                        items: vec![EmOrText::Text("#Top".to_string())],
+                        url: "#Top".to_string(),
+                        tags: hset!(["wikilink"]),
+                        ..Default::default()
+                    }),
+                ],
+                ..Default::default()
+            })],
+            ..Default::default()
+        }
+    );
+
+    #[test]
+    fn t_wikilink_diagnostics() {
+        let opts = ConvertOptions::default().wikilink_resolver(|_| None);
+        let (_, diagnostics) = convert("see [[Missing]]", &opts).unwrap();
+        assert_eq!(
+            diagnostics,
+            vec![Diagnostic {
+                kind: DiagnosticKind::UnresolvedWikilink("Missing".to_string()),
+                range: 4..15,
+            }]
+        );
+    }
+
+    macro_rules! test_md {
+        ($name:ident, $string:expr, $result:expr) => {
+            #[test]
+            fn $name() {
+                assert_eq!(doc_to_md(&parse_md_to_incodoc($string)), $result);
+            }
+        }
+    }
+
+    /// Converting back to markdown and again to incodoc gives the same document,
+    /// up to how text is split into items.
+    macro_rules! test_round_trip {
+        ($name:ident, $string:expr) => {
+            #[test]
+            fn $name() {
+                let mut doc = parse_md_to_incodoc($string);
+                let mut round = parse_md_to_incodoc(&doc_to_md(&doc));
+                doc.squash();
+                round.squash();
+                assert_eq!(round, doc);
+            }
+        }
+    }
+
+    test_md!(
+        t_doc_to_md_c0,
+        "---\ntitle: \"Hello: world\"\ncount: 3\n---\n\
+        # Head *em* `code`\n\ntext *a* **b** ~~c~~ [l](u \"t\")[^1]\n\n\
+        ## Sub\n\n- one\n- two\n\n1. x\n\n> quote\n\n[^1]: note\n",
+        "---\ncount: 3\ntitle: \"Hello: world\"\n---\n\n\
+        # Head *em* `code`\n\ntext *a* **b** ~~c~~ [l](u \"t\")[^1]\n\n\
+        ## Sub\n\n- one\n- two\n\n1. x\n\n> quote\n\n[^1]: note\n"
+    );
+
+    test_md!(
+        t_doc_to_md_c1,
+        "a \\* b \\_ c \\[d\\] \\<e\\> &amp;amp; \\`f\\` $\\$ \\|\n\n\\# no heading\n\n1\\. no list\n",
+        "a \\* b \\_ c \\[d\\] \\<e> \\&amp; \\`f\\` \\$\\$ \\|\n\n\\# no heading\n\n1\\. no list\n"
+    );
+
+    test_md!(
+        t_doc_to_md_c2,
+        "- a\n\n* b\n\n```rust,ignore {1,3-5} title=\"main file\"\nfn main() {}\n```\n\n\
+        | A | B |\n|:-|-:|\n| `x\\|y` | 2 |\n",
+        "- a\n\n* b\n\n```rust ignore {1,3-5} title=\"main file\"\nfn main() {}\n```\n\n\
+        | A | B |\n| :-- | --: |\n| `x\\|y` | 2 |\n"
+    );
+
+    test_round_trip!(
+        t_round_trip_c0,
+        "---\ntags: [a, b]\ndate: 2024-01-02\nauthor:\n  name: Me\n\
+        nav:\n  - Home: /index.html\n  - Docs:\n      - Intro: /intro\n---\n\
+        # Title *em* `code` [link](http://x.y) {#custom .cls}\n\n\
+        Some *light* **medium** ***strong*** ~~gone~~ `co`, $x^2$ and H~2~O x^2^,\n\
+        [a link](http://a.b \"title\"), <http://auto.link>, <me@mail.com>, ![alt](img.png),\n\
+        [[Wiki]], [[Wiki|label]] and a note[^1]. Special: 1 * 2 _ 3 [x] \\<y> & \\ # | $.\n"
+    );
+
+    test_round_trip!(
+        t_round_trip_c1,
+        "## Sub heading\n\n- one\n- two\n  - nested\n\n1. first\n2. second\n\n- [x] done\n- [ ] todo\n\n\
+        > quote **bold**\n\n> [!NOTE]\n> typed quote\n\n\
+        ```rust,ignore title=\"main.rs\" {1,3-5}\nfn main() {}\n```\n\n    indented code\n\n\
+        $$\n\\int x\n$$\n\n| A | B |\n|:--|--:|\n| 1 | 2 |\n\nTerm\n: Def one\n: Def two\n\n\
+        [^1]: The footnote.\n\n<div>\nhtml block\n</div>\n\n###### Deep ###\n"
+    );
+
+    test!(
+        t_quote_c8,
+        "> a\n> > b\n>\n> c\n",
+        Doc {
+            items: vec![
+                DocItem::Section(Section {
+                    heading: Heading {
+                        level: MICRO_SECTION_HEADING_LEVEL,
+                        ..Default::default()
+                    },
+                    items: vec![
+                        SectionItem::Paragraph(Paragraph {
+                            items: vec![ParagraphItem::Text("a".to_string())],
+                            ..Default::default()
+                        }),
+                        SectionItem::Section(Section {
+                            heading: Heading {
+                                level: MICRO_SECTION_HEADING_LEVEL + 1,
+                                ..Default::default()
+                            },
+                            items: vec![
+                                SectionItem::Paragraph(Paragraph {
+                                    items: vec![ParagraphItem::Text("b".to_string())],
+                                    ..Default::default()
+                                }),
+                            ],
+                            tags: hset!(["blockquote"]),
+                            ..Default::default()
+                        }),
+                        SectionItem::Paragraph(Paragraph {
+                            items: vec![ParagraphItem::Text("c".to_string())],
+                            ..Default::default()
+                        }),
+                    ],
+                    tags: hset!(["blockquote"]),
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        }
+    );
+
+    #[test]
+    fn t_deep_nesting() {
+        fn max_level(section: &Section) -> u8 {
+            section.items.iter().fold(section.heading.level, |max, item| match item {
+                SectionItem::Section(sub) => max.max(max_level(sub)),
+                SectionItem::Paragraph(_) => max,
+            })
+        }
+        let input = format!("{}deep", "> ".repeat(200));
+        let (doc, diagnostics) = convert(&input, &ConvertOptions::default()).unwrap();
+        let kinds: Vec<_> = diagnostics.into_iter().map(|d| d.kind).collect();
+        assert_eq!(kinds, vec![DiagnosticKind::DeepNesting]);
+        let DocItem::Section(section) = &doc.items[0] else { panic!("no section") };
+        assert_eq!(max_level(section), u8::MAX - 1);
+        let mut output = String::new();
+        incodoc::output::doc_out(&doc, &mut output);
+        assert!(output.contains("deep"));
+    }
+
+    test_with!(
+        t_html_map_c0,
+        ConvertOptions::default().html_blocks(HtmlBlockPolicy::Map),
+        "
+<details open>
+<summary>More &amp; <kbd>less</kbd></summary>
+
+Hidden text.
+
+</details>
+        ",
+        Doc {
+            items: vec![DocItem::Section(Section {
+                heading: Heading {
+                    level: MICRO_SECTION_HEADING_LEVEL,
+                    items: vec![EmOrText::Text("More & less".to_string())],
+                    ..Default::default()
+                },
+                items: vec![SectionItem::Paragraph(Paragraph {
+                    items: vec![ParagraphItem::Text("Hidden text.".to_string())],
+                    ..Default::default()
+                })],
+                tags: hset!(["details", "details-open"]),
+                ..Default::default()
+            })],
+            ..Default::default()
+        }
+    );
+
+    test_with!(
+        t_html_map_c1,
+        ConvertOptions::default().html_blocks(HtmlBlockPolicy::Map),
+        r#"
+<p align="center">
+  <a href="https://example.org"><img src="logo.png" alt="Logo"></a><br>
+  Press <kbd>Ctrl</kbd> + <img src="c.png" title="C key" />
+</p>
+        "#,
+        Doc {
+            items: vec![DocItem::Paragraph(Paragraph {
+                items: vec![
+                    ParagraphItem::Link(Link {
+                        url: "https://example.org".to_string(),
+                        items: vec![EmOrText::Text("Logo".to_string())],
+                        props: props!([
+                            ("image".to_string(), PropVal::String("logo.png".to_string())),
+                        ]),
+                        ..Default::default()
+                    }),
+                    ParagraphItem::Text("\nPress ".to_string()),
+                    ParagraphItem::MText(TextWithMeta {
+                        text: "Ctrl".to_string(),
+                        tags: hset!(["kbd"]),
+                        ..Default::default()
+                    }),
+                    ParagraphItem::Text(" + ".to_string()),
+                    ParagraphItem::Link(Link {
+                        url: "c.png".to_string(),
+                        tags: hset!(["image"]),
+                        props: props!([
+                            ("title".to_string(), PropVal::String("C key".to_string())),
+                        ]),
+                        ..Default::default()
+                    }),
+                ],
+                props: props!([
+                    ("align".to_string(), PropVal::String("center".to_string())),
+                ]),
+                ..Default::default()
+            })],
+            ..Default::default()
+        }
+    );
+
+    test_with!(
+        t_html_map_c2,
+        ConvertOptions::default().html_blocks(HtmlBlockPolicy::Map),
+        r#"
+<table>
+  <tr><th>a</th><th align="right">b</th></tr>
+  <tr><td>1</td><td>2</td></tr>
+</table>
+        "#,
+        Doc {
+            items: vec![DocItem::Paragraph(Paragraph {
+                items: vec![ParagraphItem::Table(Table {
+                    rows: vec![
+                        TableRow {
+                            items: vec![
+                                Paragraph {
+                                    items: vec![ParagraphItem::Text("a".to_string())],
+                                    ..Default::default()
+                                },
+                                Paragraph {
+                                    items: vec![ParagraphItem::Text("b".to_string())],
+                                    props: props!([
+                                        ("align".to_string(), PropVal::String("right".to_string())),
+                                    ]),
+                                    ..Default::default()
+                                },
+                            ],
+                            is_header: true,
+                            ..Default::default()
+                        },
+                        TableRow {
+                            items: vec![
+                                Paragraph {
+                                    items: vec![ParagraphItem::Text("1".to_string())],
+                                    ..Default::default()
+                                },
+                                Paragraph {
+                                    items: vec![ParagraphItem::Text("2".to_string())],
+                                    ..Default::default()
+                                },
+                            ],
+                            ..Default::default()
+                        },
+                    ],
+                    ..Default::default()
+                })],
+                ..Default::default()
+            })],
+            ..Default::default()
+        }
+    );
+
+    test_with!(
+        t_html_map_c3,
+        ConvertOptions::default().html_blocks(HtmlBlockPolicy::Map),
+        "
+> <details>
+>
+> inside
+
+</details>
+        ",
+        Doc {
+            items: vec![DocItem::Section(Section {
+                heading: Heading {
+                    level: MICRO_SECTION_HEADING_LEVEL,
+                    ..Default::default()
+                },
+                items: vec![
+                    SectionItem::Section(Section {
+                        heading: Heading {
+                            level: MICRO_SECTION_HEADING_LEVEL + 1,
+                            ..Default::default()
+                        },
+                        items: vec![SectionItem::Paragraph(Paragraph {
+                            items: vec![ParagraphItem::Text("inside".to_string())],
+                            ..Default::default()
+                        })],
+                        tags: hset!(["details"]),
+                        ..Default::default()
+                    }),
+                ],
+                tags: hset!(["blockquote"]),
+                ..Default::default()
+            }),
+            DocItem::Paragraph(Paragraph {
+                items: vec![ParagraphItem::Code(Ok(CodeBlock {
+                    language: "html".to_string(),
+                    mode: CodeModeHint::Show,
+                    code: "</details>\n".to_string(),
+                    tags: hset!(["unconv-corp"]),
+                    ..Default::default()
+                }))],
+                ..Default::default()
+            })],
+            ..Default::default()
+        }
+    );
+
+    #[test]
+    fn t_doc_to_md_details() {
+        let opts = ConvertOptions::default().html_blocks(HtmlBlockPolicy::Map);
+        let md = "<details open>\n<summary>A &lt; B</summary>\n\nHidden.\n\n</details>\n";
+        assert_eq!(doc_to_md(&parse_md_to_incodoc_with(md, &opts)), md);
+    }
+
+
+    test!(
+        t_inline_html_c0,
+        "a <b>bold</b> <sup>2</sup> <code>x</code> <kbd>K</kbd>\n",
+        Doc {
+            items: vec![DocItem::Paragraph(Paragraph {
+                items: vec![
+                    ParagraphItem::Text("a ".to_string()),
+                    ParagraphItem::Em(Emphasis {
+                        strength: EmStrength::Medium,
+                        etype: EmType::Emphasis,
+                        text: "bold".to_string(),
+                        ..Default::default()
+                    }),
+                    ParagraphItem::Text(" ".to_string()),
+                    ParagraphItem::MText(TextWithMeta {
+                        text: "2".to_string(),
+                        tags: hset!(["super"]),
+                        ..Default::default()
+                    }),
+                    ParagraphItem::Text(" ".to_string()),
+                    ParagraphItem::MText(TextWithMeta {
+                        text: "x".to_string(),
+                        tags: hset!(["code"]),
+                        ..Default::default()
+                    }),
+                    ParagraphItem::Text(" ".to_string()),
+                    ParagraphItem::MText(TextWithMeta {
+                        text: "K".to_string(),
+                        tags: hset!(["kbd"]),
+                        ..Default::default()
+                    }),
+                ],
+                ..Default::default()
+            })],
+            ..Default::default()
+        }
+    );
+
+    test!(
+        t_inline_html_c1,
+        "a<br>\nb<br/>c <a href=\"u\" title=\"t\">link <img src=\"i.png\" alt=\"I\"></a>\n",
+        Doc {
+            items: vec![DocItem::Paragraph(Paragraph {
+                items: vec![
+                    ParagraphItem::Text("a".to_string()),
+                    ParagraphItem::Text("\n".to_string()),
+                    ParagraphItem::Text("b".to_string()),
+                    ParagraphItem::Text("\n".to_string()),
+                    ParagraphItem::Text("c ".to_string()),
+                    ParagraphItem::Link(Link {
+                        url: "u".to_string(),
+                        items: vec![
+                            EmOrText::Text("link ".to_string()),
+                            EmOrText::Text("I".to_string()),
+                        ],
+                        props: props!([
+                            ("title".to_string(), PropVal::String("t".to_string())),
+                            ("image".to_string(), PropVal::String("i.png".to_string())),
+                        ]),
+                        ..Default::default()
+                    }),
+                ],
+                ..Default::default()
+            })],
+            ..Default::default()
+        }
+    );
+
+    test!(
+        t_inline_html_c2,
+        "a <span>s<img src=\"i.png\"></span> <hr> <!-- c --> </div> d <br>\n",
+        Doc {
+            items: vec![DocItem::Paragraph(Paragraph {
+                items: vec![
+                    ParagraphItem::Text("a ".to_string()),
+                    ParagraphItem::Em(Emphasis {
+                        strength: EmStrength::Light,
+                        etype: EmType::Deemphasis,
+                        text: "html(".to_string(),
+                        ..Default::default()
+                    }),
+                    ParagraphItem::Text("s".to_string()),
+                    ParagraphItem::Link(Link {
+                        url: "i.png".to_string(),
+                        tags: hset!(["image"]),
+                        ..Default::default()
+                    }),
+                    ParagraphItem::Em(Emphasis {
+                        strength: EmStrength::Light,
+                        etype: EmType::Deemphasis,
+                        text: ")".to_string(),
+                        ..Default::default()
+                    }),
+                    ParagraphItem::Text(" ".to_string()),
+                    ParagraphItem::Em(Emphasis {
+                        strength: EmStrength::Light,
+                        etype: EmType::Deemphasis,
+                        text: "html(".to_string(),
+                        ..Default::default()
+                    }),
+                    ParagraphItem::Em(Emphasis {
+                        strength: EmStrength::Light,
+                        etype: EmType::Deemphasis,
+                        text: ")".to_string(),
+                        ..Default::default()
+                    }),
+                    ParagraphItem::Text(" ".to_string()),
+                    ParagraphItem::Text(" ".to_string()),
+                    ParagraphItem::Text(" d ".to_string()),
+                ],
+                ..Default::default()
+            })],
+            ..Default::default()
+        }
+    );
+
+    fn collected_ref(label: &str, number: &str, k: usize) -> ParagraphItem {
+        ParagraphItem::Link(Link {
+            url: format!("#footnote-{label}"),
+            items: vec![EmOrText::Text(number.to_string())],
+            tags: hset!(["footnote-ref"]),
+            props: props!([
+                ("id".to_string(), PropVal::String(format!("footnote-ref-{label}-{k}"))),
+            ]),
+        })
+    }
+
+    fn collected_backref(label: &str, k: usize) -> ParagraphItem {
+        ParagraphItem::Link(Link {
+            url: format!("#footnote-ref-{label}-{k}"),
+            items: vec![EmOrText::Text("↩".to_string())],
+            tags: hset!(["footnote-backref"]),
+            ..Default::default()
+        })
+    }
+
+    fn collected_def(
+        label: &str, number: &str, referenced: bool, items: Vec<SectionItem>
+    ) -> SectionItem {
+        let mut tags = hset!(["footnote-def"]);
+        if !referenced {
+            tags.insert("unreferenced-footnote".to_string());
+        }
+        SectionItem::Section(Section {
+            heading: Heading {
+                level: MICRO_SECTION_HEADING_LEVEL + 1,
+                items: vec![EmOrText::Text(number.to_string())],
+                ..Default::default()
+            },
+            items,
+            tags,
+            props: props!([
+                ("id".to_string(), PropVal::String(format!("footnote-{label}"))),
+            ]),
+        })
+    }
+
+    fn collected_footnotes(defs: Vec<SectionItem>) -> DocItem {
+        DocItem::Section(Section {
+            heading: Heading {
+                level: MICRO_SECTION_HEADING_LEVEL,
+                items: vec![EmOrText::Text("Footnotes".to_string())],
+                ..Default::default()
+            },
+            items: defs,
+            tags: hset!(["footnotes"]),
+            ..Default::default()
+        })
+    }
+
+    fn text_par(items: Vec<ParagraphItem>) -> SectionItem {
+        SectionItem::Paragraph(Paragraph { items, ..Default::default() })
+    }
+
+    test_with!(
+        t_collect_footnotes_c0,
+        ConvertOptions::default().collect_footnotes(true),
+        "b[^b] a[^a] b[^b]\n\n[^a]: A\n\n[^b]: B\n",
+        Doc {
+            items: vec![
+                DocItem::Paragraph(Paragraph {
+                    items: vec![
+                        ParagraphItem::Text("b".to_string()),
+                        collected_ref("b", "1", 1),
+                        ParagraphItem::Text(" a".to_string()),
+                        collected_ref("a", "2", 1),
+                        ParagraphItem::Text(" b".to_string()),
+                        collected_ref("b", "1", 2),
+                    ],
+                    ..Default::default()
+                }),
+                collected_footnotes(vec![
+                    collected_def("b", "1", true, vec![
+                        text_par(vec![ParagraphItem::Text("B".to_string())]),
+                        text_par(vec![
+                            collected_backref("b", 1),
+                            ParagraphItem::Text(" ".to_string()),
+                            collected_backref("b", 2),
+                        ]),
+                    ]),
+                    collected_def("a", "2", true, vec![
+                        text_par(vec![ParagraphItem::Text("A".to_string())]),
+                        text_par(vec![collected_backref("a", 1)]),
+                    ]),
+                ]),
+            ],
+            ..Default::default()
+        }
+    );
+
+    test_with!(
+        t_collect_footnotes_c1,
+        ConvertOptions::default().collect_footnotes(true),
+        "# H\n\n[^x]: X\n\ntext[^y]\n\n[^y]: Y\n",
+        Doc {
+            items: vec![
+                DocItem::Section(Section {
+                    heading: Heading {
+                        level: 0,
+                        items: vec![EmOrText::Text("H".to_string())],
+                        ..Default::default()
+                    },
+                    items: vec![text_par(vec![
+                        ParagraphItem::Text("text".to_string()),
+                        collected_ref("y", "1", 1),
+                    ])],
+                    ..Default::default()
+                }),
+                collected_footnotes(vec![
+                    collected_def("y", "1", true, vec![
+                        text_par(vec![ParagraphItem::Text("Y".to_string())]),
+                        text_par(vec![collected_backref("y", 1)]),
+                    ]),
+                    collected_def("x", "2", false, vec![
+                        text_par(vec![ParagraphItem::Text("X".to_string())]),
+                    ]),
+                ]),
+            ],
+            ..Default::default()
+        }
+    );
+
+    test_with!(
+        t_collect_footnotes_c2,
+        ConvertOptions::default().collect_footnotes(true),
+        "a[^x] b[^1]\n\n[^1]: one\n\n[^2]: two\n",
+        Doc {
+            items: vec![
+                DocItem::Paragraph(Paragraph {
+                    items: vec![
+                        ParagraphItem::Text("a".to_string()),
+                        ParagraphItem::Link(Link {
+                            url: "#footnote-x".to_string(),
+                            items: vec![EmOrText::Text("x".to_string())],
+                            tags: hset!(["footnote-ref", "dangling-footnote-ref"]),
+                            ..Default::default()
+                        }),
+                        ParagraphItem::Text(" b".to_string()),
+                        collected_ref("1", "1", 1),
+                    ],
+                    ..Default::default()
+                }),
+                collected_footnotes(vec![
+                    collected_def("1", "1", true, vec![
+                        text_par(vec![ParagraphItem::Text("one".to_string())]),
+                        text_par(vec![collected_backref("1", 1)]),
+                    ]),
+                    collected_def("2", "2", false, vec![
+                        text_par(vec![ParagraphItem::Text("two".to_string())]),
+                    ]),
+                ]),
+            ],
+            ..Default::default()
+        }
+    );
+
+    #[test]
+    fn t_doc_to_md_collected_footnotes() {
+        let opts = ConvertOptions::default().collect_footnotes(true);
+        let md = "a[^n] b[^m]\n\n[^m]: M\n\n[^n]: N\n";
+        let expected = "a[^n] b[^m]\n\n[^n]: N\n\n[^m]: M\n";
+        assert_eq!(doc_to_md(&parse_md_to_incodoc_with(md, &opts)), expected);
+    }
+
+    #[test]
+    fn t_collect_footnotes_heading_ids() {
+        let opts = ConvertOptions::default().collect_footnotes(true).heading_ids(true);
+        let md = "# H\n\na[^n] [back](#footnote-ref-n-1)\n\n[^n]: N\n";
+        let (mut doc, diagnostics) = convert(md, &opts).unwrap();
+        assert_eq!(diagnostics, vec![]);
+        let links = doc.links_mut(false);
+        assert_eq!(links.len(), 3);
+        assert!(links.iter().all(|link| !link.tags.contains("broken-anchor")));
+    }
+
+    test_with!(
+        t_link_definitions_c0,
+        ConvertOptions::default().link_definitions(true).link_types(true),
+        "[a][Foo] [foo] <http://x.y>\n\n[Foo]: /url \"T\"\n",
+        Doc {
+            items: vec![DocItem::Paragraph(Paragraph {
+                items: vec![
+                    ParagraphItem::Link(Link {
+                        url: "/url".to_string(),
+                        items: vec![EmOrText::Text("a".to_string())],
+                        props: props!([
+                            ("link-type".to_string(), PropVal::String("reference".to_string())),
+                            ("link-ref".to_string(), PropVal::String("Foo".to_string())),
+                            ("title".to_string(), PropVal::String("T".to_string())),
+                        ]),
+                        ..Default::default()
+                    }),
+                    ParagraphItem::Text(" ".to_string()),
+                    ParagraphItem::Link(Link {
+                        url: "/url".to_string(),
+                        items: vec![EmOrText::Text("foo".to_string())],
+                        props: props!([
+                            ("link-type".to_string(), PropVal::String("shortcut".to_string())),
+                            ("link-ref".to_string(), PropVal::String("foo".to_string())),
+                            ("title".to_string(), PropVal::String("T".to_string())),
+                        ]),
+                        ..Default::default()
+                    }),
+                    ParagraphItem::Text(" ".to_string()),
+                    ParagraphItem::Link(Link {
+                        url: "http://x.y".to_string(),
+                        items: vec![EmOrText::Text("http://x.y".to_string())],
+                        props: props!([
+                            ("link-type".to_string(), PropVal::String("autolink".to_string())),
+                        ]),
+                        ..Default::default()
+                    }),
+                ],
+                ..Default::default()
+            })],
+            navs: vec![Nav {
+                description: "link definitions".to_string(),
+                links: vec![Link {
+                    url: "/url".to_string(),
+                    items: vec![EmOrText::Text("Foo".to_string())],
+                    props: props!([
+                        ("title".to_string(), PropVal::String("T".to_string())),
+                    ]),
+                    ..Default::default()
+                }],
+                tags: hset!(["link-definitions"]),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    );
+
+    #[test]
+    fn t_doc_to_md_link_references() {
+        let opts = ConvertOptions::default().link_definitions(true).link_types(true);
+        let md = "[a][Foo], [foo][], [foo]\\(x), [x](/x), <http://y.z> and ![i][foo].\n\n\
+            [Foo]: /url \"T\"\n[unused]: </b r>\n";
+        let mut doc = parse_md_to_incodoc_with(md, &opts);
+        assert_eq!(doc_to_md(&doc), md);
+        // a link that no longer matches its definition is written inline
+        doc.links_mut(false)[0].url = "/other".to_string();
+        let expected = "[a](/other \"T\"), [foo][], [foo]\\(x), [x](/x), <http://y.z> and \
+            ![i][foo].\n\n[Foo]: /url \"T\"\n[unused]: </b r>\n";
+        assert_eq!(doc_to_md(&doc), expected);
+    }
+
+    #[test]
+    fn t_doc_to_md_link_references_without_definitions() {
+        let opts = ConvertOptions::default().link_types(true);
+        let md = "[a][x] [b][X]\n\n[x]: /u\n";
+        assert_eq!(doc_to_md(&parse_md_to_incodoc_with(md, &opts)), md);
+    }
+
+    fn link_urls(doc: &mut Doc) -> Vec<String> {
+        doc.links_mut(true).into_iter().map(|link| link.url.clone()).collect()
+    }
+
+    #[test]
+    fn t_rewrite_links_c0() {
+        let opts = ConvertOptions::default()
+            .link_extension("md", "incodoc")
+            .base_url("https://docs.example/guide/intro.md");
+        let md = "+++\nnav N\nlink Home $ ../index.md\nend\n+++\n\
+            # [Setup](./setup.md)\n\n\
+            [a](../api/foo.md#s) [b](/abs.md?q) [c](#frag) [d](https://e.org/x.md) \
+            <me@x.org> ![i](img/p.png) [e](a/../b/)\n";
+        let mut doc = parse_md_to_incodoc_with(md, &opts);
+        assert_eq!(link_urls(&mut doc), [
+            "https://docs.example/index.incodoc",
+            "https://docs.example/api/foo.incodoc#s",
+            "https://docs.example/abs.incodoc?q",
+            "#frag",
+            "https://e.org/x.md",
+            "me@x.org",
+            "https://docs.example/guide/img/p.png",
+            "https://docs.example/guide/b/",
+        ]);
+        let Some(DocItem::Section(section)) = doc.items.first() else { panic!("no section") };
+        assert_eq!(
+            section.heading.props.get("link-0"),
+            Some(&PropVal::String("https://docs.example/guide/setup.incodoc".to_string())),
+        );
+    }
+
+    #[test]
+    fn t_rewrite_links_c1() {
+        let opts = ConvertOptions::default()
+            .link_extension("md", "incodoc")
+            .link_rewriter(|url| url.replace("incodoc", "html"));
+        let mut doc = parse_md_to_incodoc_with("[a](../a.md) [b](//host/b.md) [c](c.MD)\n", &opts);
+        assert_eq!(link_urls(&mut doc), ["../a.html", "//host/b.md", "c.MD"]);
+    }
+
+    #[test]
+    fn t_rewrite_links_c2() {
+        let opts = ConvertOptions::default().base_url("https://e.org/d/");
+        let mut doc = parse_md_to_incodoc_with("<a href=\"a.md\"><img src=\"p.png\"></a>\n", &opts);
+        let links = doc.links_mut(true);
+        assert_eq!(links[0].url, "https://e.org/d/a.md");
+        assert_eq!(
+            links[0].props.get("image"),
+            Some(&PropVal::String("https://e.org/d/p.png".to_string())),
+        );
+    }
+
+    test!(
+        t_image_props_c0,
+        "![An *img*](<p.png =100x>){width=50% height=\"2 em\" .c}\n\n*The caption*\n",
+        Doc {
+            items: vec![DocItem::Paragraph(Paragraph {
+                items: vec![ParagraphItem::Link(Link {
+                    items: vec![
+                        EmOrText::Text("An ".to_string()),
+                        EmOrText::Em(Emphasis {
+                            text: "img".to_string(),
+                            ..Default::default()
+                        }),
+                    ],
+                    url: "p.png".to_string(),
+                    tags: hset!(["image"]),
+                    props: props!([
+                        ("alt".to_string(), PropVal::String("An img".to_string())),
+                        ("width".to_string(), PropVal::String("50%".to_string())),
+                        ("height".to_string(), PropVal::String("2 em".to_string())),
+                        ("class".to_string(), PropVal::String("c".to_string())),
+                    ]),
+                })],
+                tags: hset!(["figure"]),
+                props: props!([
+                    ("caption".to_string(), PropVal::String("The caption".to_string())),
+                ]),
+            })],
+            ..Default::default()
+        }
+    );
+
+    test!(
+        t_image_props_c1,
+        "a ![b](<q.png =x20>){#id} <img src=\"r.png\" alt=\"R\" width=\"30\">\n\n*not a caption*\n",
+        Doc {
+            items: vec![
+                DocItem::Paragraph(Paragraph {
+                    items: vec![
+                        ParagraphItem::Text("a ".to_string()),
+                        ParagraphItem::Link(Link {
+                            items: vec![EmOrText::Text("b".to_string())],
+                            url: "q.png".to_string(),
+                            tags: hset!(["image"]),
+                            props: props!([
+                                ("alt".to_string(), PropVal::String("b".to_string())),
+                                ("height".to_string(), PropVal::Int(20)),
+                                ("id".to_string(), PropVal::String("id".to_string())),
+                            ]),
+                        }),
+                        ParagraphItem::Text(" ".to_string()),
+                        ParagraphItem::Link(Link {
+                            items: vec![EmOrText::Text("R".to_string())],
+                            url: "r.png".to_string(),
+                            tags: hset!(["image"]),
+                            props: props!([
+                                ("alt".to_string(), PropVal::String("R".to_string())),
+                                ("width".to_string(), PropVal::Int(30)),
+                            ]),
+                        }),
+                    ],
+                    ..Default::default()
+                }),
+                DocItem::Paragraph(Paragraph {
+                    items: vec![ParagraphItem::Em(Emphasis {
+                        text: "not a caption".to_string(),
+                        ..Default::default()
+                    })],
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        }
+    );
+
+    #[test]
+    fn t_doc_to_md_figure() {
+        let md = "![a](p.png){width=50% height=\"2 em\"}\n\n*A caption*\n\n\
+            b ![c](q.png){height=1}, d\n";
+        assert_eq!(doc_to_md(&parse_md_to_incodoc(md)), md);
+    }
+
+    test_md!(
+        t_doc_to_md_image_attributes,
+        "![a](p.png){.c #i width=5 k=\"v w\" .d}\n\n**Bold**\n\nb ![c](q.png){x .e}\n",
+        "![a](p.png){#i .c .d width=5 k=\"v w\"}\n\n**Bold**\n\nb ![c](q.png)\\{x .e}\n"
+    );
+
+    test!(
+        t_inline_html_c3,
+        "a <b>bold never closed\n\nnext paragraph\n",
+        Doc {
+            items: vec![
+                DocItem::Paragraph(Paragraph {
+                    items: vec![
+                        ParagraphItem::Text("a ".to_string()),
+                        ParagraphItem::Em(Emphasis {
+                            strength: EmStrength::Medium,
+                            etype: EmType::Emphasis,
+                            text: "bold never closed".to_string(),
+                            ..Default::default()
+                        }),
+                    ],
+                    ..Default::default()
+                }),
+                DocItem::Paragraph(Paragraph {
+                    items: vec![ParagraphItem::Text("next paragraph".to_string())],
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        }
+    );
+
+    #[test]
+    fn t_doc_to_md_unclosed_html() {
+        for (md, expected) in [
+            ("- item <kbd>k\n- other\n\nafter\n", "- item <kbd>k</kbd>\n- other\n\nafter\n"),
+            ("- a <b>x\n  - sub\n\nend\n", "- a **x**\n  - sub\n\nend\n"),
+            ("> q <i>open <a href=\"u\">link\n\nz\n", "> q *open* [*link*](u)\n\nz\n"),
+            ("`a `<code>b</code> <code>c\nd\n", "`a `<code>b</code> `c d`\n"),
+            ("a <b>x [l](u) y\n", "a **x** [**l**](u) **y**\n"),
+            ("**A [0](u) 0**\n", "**A** [**0**](u) **0**\n"),
+            ("**x `c` y**\n", "**x** `c` **y**\n"),
+            (
+                "<b>a\n<br> b\n\n<kbd>c\n <br> d\n",
+                "**a**\n\n**b**\n\n<kbd>c</kbd>\n\n<kbd>d</kbd>\n",
+            ),
+            ("# <code>a <sup>b\n", "# `a `<code>b</code>\n"),
+            ("# # <br>\n", "# \\#\n"),
+        ] {
+            assert_eq!(doc_to_md(&parse_md_to_incodoc(md)), expected);
+        }
+    }
+
+    test_md!(
+        t_doc_to_md_heading_links,
+        "# See [a *b*](u \"t\") end[^n] ![i](p.png) [](e)\n\nSet [x\ny](v)\n===\n\n[^n]: note\n",
+        "# See [a *b*](u \"t\") end[^n] ![i](p.png) [](e)\n\n# Set [x y](v)\n\n[^n]: note\n"
+    );
+
+    test_md!(
+        t_doc_to_md_flanking,
+        "_)<http://a.b>$![i](p.png)*e*(_&amp;\n\nx**a**<b>y</b>z *a*<i>b</i> ~~a~~<del>b</del>\n",
+        "*)*<http://a.b>*\\$*![*i*](p.png)**e**_(_&\n\n\
+        x**a**__y__&#122; *a*_b_ ~~a~~<del>b</del>\n"
+    );
+
+    test_md!(
+        t_doc_to_md_block_starts,
+        "# a }\n\n# b $$$$\n\n> [!NOTE]\n>\n> -\n\n1. c\n   - d\n\n   e\n\n\
+        | <sup>f<br>1.</sup> |\n|---|\n\n> >\n>\n> g\n",
+        "# a \\}\n\n# b $$$$\n\n> [!NOTE]\n>\n> -\n\n1. c\n   - d\n\n   e\n\n\
+        | ^f 1.^ |\n| --- |\n\n> >\n>\n> g\n"
+    );
+
+    test_md!(
+        t_doc_to_md_split_deflist,
+        "a\n\n: # b\n\n  c\n",
+        "a\n\n# b\n\nc\n"
+    );
+
+    test_diagnostics!(
+        t_diagnostics_heading,
+        "# a ![i](p.png){width=5}",
+        vec![Diagnostic {
+            kind: DiagnosticKind::Unsupported("image size in heading".to_string()),
+            range: 0..24,
+        }]
+    );
+
+    #[test]
+    fn t_html_unsafe_urls() {
+        let md = "a <a href=\" JavaScript:alert(1)\">x</a> <img src=\"data:text/html,h\"> \
+            <img src=\"data:image/png;base64,AA\">\n";
+        let mut doc = parse_md_to_incodoc(md);
+        assert_eq!(link_urls(&mut doc), ["", "data:image/png;base64,AA"]);
+        let opts = ConvertOptions::default().html_blocks(HtmlBlockPolicy::Map);
+        let md = "<p><a href=\"java&#9;script:x\">y</a> <a href=\"vbscript:z\">\
+            <img src=\"i.png\"></a> <a href=\"data:image/png,x\">d</a></p>\n";
+        let mut doc = parse_md_to_incodoc_with(md, &opts);
+        assert_eq!(link_urls(&mut doc), ["", "", ""]);
+    }
+
+    #[test]
+    fn t_doc_to_incodoc() {
+        let md = "# T\n\n> quote\n\n## S\n\n-\n\n| a |\n|---|\n|   |\n\n[](u)\n";
+        let doc = parse_md_to_incodoc(md);
+        assert_eq!(incodoc::parsing::parse(&doc_to_incodoc(&doc)), Ok(doc));
+    }
+}
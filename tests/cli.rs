@@ -0,0 +1,24 @@
+use std::{
+    io::Write,
+    process::{ Command, Stdio },
+};
+
+fn run(args: &[&str], input: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_md-to-incodoc"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "md-to-incodoc {args:?} exited with {}", output.status);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn t_cli_round_trip() {
+    let md = "# T\n\n> quote\n\n## Only a heading\n\n-\n- b\n\n[](u)\n";
+    let incodoc = run(&["-q"], md);
+    assert_eq!(run(&["--to-markdown"], &incodoc), md);
+}